@@ -0,0 +1,233 @@
+use crate::{hack, unwrap_borrow, BorrowMutError};
+use alloc::sync::Arc;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::Ordering;
+
+pub trait ArcBorrowMut<T: ?Sized> {
+    /// Mutably borrows the contents of the Arc.
+    ///
+    /// # Panics
+    ///
+    /// If there are other strong references.
+    #[track_caller]
+    fn borrow_mut(me: &mut Self) -> ArcBorrowRefMut<'_, T> {
+        Self::try_borrow_mut(me).unwrap()
+    }
+
+    /// Mutably borrows the contents of the Arc.
+    ///
+    /// Succeeds if the argument is the only strong reference, and the layout of `Arc`
+    /// (checked once) is as expected. While borrowed,
+    /// `Weak::upgrade` fails on every thread.
+    #[track_caller]
+    fn try_borrow_mut(me: &mut Self) -> Result<ArcBorrowRefMut<'_, T>, BorrowMutError>;
+
+    /// Mutably borrows the contents of the Arc for the duration of `f`.
+    ///
+    /// Unlike [`ArcBorrowMut::borrow_mut`], the strong count is always restored
+    /// before this returns (or unwinds), since the guard is never exposed.
+    ///
+    /// # Panics
+    ///
+    /// If there are other strong references.
+    #[track_caller]
+    fn with_borrow_mut<R>(me: &mut Self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut mutable = Self::borrow_mut(me);
+        f(&mut mutable)
+    }
+
+    /// Mutably borrows the contents of the Arc for the duration of `f`.
+    ///
+    /// Succeeds if the argument is the only strong reference.
+    #[track_caller]
+    fn try_with_borrow_mut<R>(
+        me: &mut Self,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> Result<R, BorrowMutError> {
+        let mut mutable = Self::try_borrow_mut(me)?;
+        Ok(f(&mut mutable))
+    }
+}
+
+/// A mutable handle to the contents of an `Arc`.
+///
+/// While it exists, the strong count is zero and the weak count holds an extra reference, so
+/// that forgetting the handle leaks the allocation instead of freeing it.
+pub struct ArcBorrowRefMut<'a, T: ?Sized> {
+    inner: &'a mut Arc<T>,
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ArcBorrowRefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for ArcBorrowRefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for ArcBorrowRefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        let raw = Arc::as_ptr(self.inner);
+        unsafe { &*raw }
+    }
+}
+
+impl<T: ?Sized> DerefMut for ArcBorrowRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        let raw = Arc::as_ptr(self.inner);
+        unsafe { &mut *(raw as *mut T) }
+    }
+}
+
+impl<T: ?Sized> Drop for ArcBorrowRefMut<'_, T> {
+    fn drop(&mut self) {
+        let raw = Arc::as_ptr(self.inner);
+        unsafe {
+            let arc_inner = hack::raw_to_arc_inner(raw);
+            // Publishes our writes to whoever upgrades a `Weak` next.
+            let _old = (*arc_inner).strong.swap(1, Ordering::Release);
+            debug_assert_eq!(_old, 0);
+            let _old = (*arc_inner).weak.fetch_sub(1, Ordering::Release);
+            debug_assert!(_old > 1);
+        }
+    }
+}
+
+impl<T: ?Sized> ArcBorrowMut<T> for Arc<T> {
+    #[track_caller]
+    fn borrow_mut(me: &mut Self) -> ArcBorrowRefMut<'_, T> {
+        let value = Arc::as_ptr(me).cast::<()>();
+        unwrap_borrow(Self::try_borrow_mut(me), value)
+    }
+
+    #[track_caller]
+    fn try_borrow_mut(me: &mut Self) -> Result<ArcBorrowRefMut<'_, T>, BorrowMutError> {
+        if !hack::arc_layout_matches() {
+            return Err(BorrowMutError::LayoutMismatch);
+        }
+
+        unsafe {
+            let raw = Arc::as_ptr(me);
+            let arc_inner = hack::raw_to_arc_inner(raw);
+            // A `Weak` may be upgraded on another thread at any time, so this must be atomic.
+            // Acquire synchronizes with the release of any strong reference that was dropped.
+            match (*arc_inner)
+                .strong
+                .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => {}
+                Err(0) => return Err(BorrowMutError::AlreadyBorrowed),
+                Err(strong_count) => {
+                    return Err(BorrowMutError::OtherStrongReferencesExist {
+                        strong_count,
+                        weak_count: Arc::weak_count(me),
+                    })
+                }
+            }
+            // Keeps the allocation alive if `ArcBorrowRefMut` is leaked.
+            (*arc_inner).weak.fetch_add(1, Ordering::Relaxed);
+
+            Ok(ArcBorrowRefMut { inner: me })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ArcBorrowMut, BorrowMutError};
+    use std::format;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn mutate() {
+        let mut arc = Arc::new(0);
+        let weak = Arc::downgrade(&Arc::clone(&arc));
+        assert!(Arc::get_mut(&mut arc).is_none());
+        let mut mutable = Arc::borrow_mut(&mut arc);
+        *mutable += 1;
+        assert!(weak.upgrade().is_none());
+        assert_eq!(format!("{} {:?}", mutable, mutable), "1 1");
+        drop(mutable);
+        assert_eq!(*weak.upgrade().unwrap(), 1);
+        assert_eq!(Arc::strong_count(&arc), 1);
+        assert_eq!(Arc::weak_count(&arc), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot borrow mutably, other strong references exist \
+        (strong count: 2, weak count: 0)")]
+    fn panic() {
+        let mut arc = Arc::new(0);
+        let _arc2 = Arc::clone(&arc);
+        let _ = Arc::borrow_mut(&mut arc);
+    }
+
+    #[test]
+    fn error() {
+        let mut arc = Arc::new(0);
+        let _arc2 = Arc::clone(&arc);
+        assert_eq!(
+            Arc::try_borrow_mut(&mut arc).unwrap_err(),
+            BorrowMutError::OtherStrongReferencesExist {
+                strong_count: 2,
+                weak_count: 0
+            }
+        );
+    }
+
+    #[test]
+    fn unsize() {
+        let mut arc: Arc<str> = "asdf".into();
+        let _weak = Arc::downgrade(&arc);
+        Arc::with_borrow_mut(&mut arc, |s| s.make_ascii_uppercase());
+        assert_eq!(&*arc, "ASDF");
+    }
+
+    #[test]
+    fn concurrent_upgrade() {
+        let mut arc = Arc::new([0u64; 4]);
+        let done = Arc::new(AtomicBool::new(false));
+        let barrier = Arc::new(Barrier::new(3));
+        let threads = (0..2)
+            .map(|_| {
+                let weak = Arc::downgrade(&arc);
+                let done = Arc::clone(&done);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    while !done.load(Ordering::Relaxed) {
+                        if let Some(arc) = weak.upgrade() {
+                            assert!(arc.iter().all(|&n| n == arc[0]));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        barrier.wait();
+
+        let mut borrowed = 0;
+        for _ in 0..1000 {
+            if let Ok(mut mutable) = Arc::try_borrow_mut(&mut arc) {
+                borrowed += 1;
+                for n in mutable.iter_mut() {
+                    *n += 1;
+                }
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(arc.iter().all(|&n| n == borrowed));
+    }
+}
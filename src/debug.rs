@@ -0,0 +1,124 @@
+use alloc::rc::Weak;
+use core::panic::Location;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+
+/// Where each mutably borrowed `Rc` was borrowed, keyed by the address of its value.
+static LOCATIONS: Mutex<BTreeMap<usize, &'static Location<'static>>> = Mutex::new(BTreeMap::new());
+
+pub(crate) fn record(value: usize, location: &'static Location<'static>) {
+    // A panic elsewhere doesn't invalidate the table.
+    let mut locations = LOCATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    locations.insert(value, location);
+}
+
+pub(crate) fn remove(value: usize) {
+    let mut locations = LOCATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    locations.remove(&value);
+}
+
+pub(crate) fn location(value: usize) -> Option<&'static Location<'static>> {
+    let locations = LOCATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    locations.get(&value).copied()
+}
+
+/// Returns where the contents of the `Rc` that `weak` points to were mutably borrowed, if they
+/// still are. Useful for finding out why `Weak::upgrade` returns `None`.
+pub fn active_borrow_location<T: ?Sized>(weak: &Weak<T>) -> Option<&'static Location<'static>> {
+    location(Weak::as_ptr(weak).cast::<()>().addr())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::leak;
+    use crate::{
+        active_borrow_location, borrow_mut_iter, borrow_mut_many, BorrowRefMut, PinRcBorrowMut,
+        Poison, RcBorrowMut,
+    };
+    use core::panic::Location;
+    use core::pin::Pin;
+    use std::format;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::rc::{Rc, Weak};
+    use std::string::String;
+
+    fn line<T: ?Sized>(weak: &Weak<T>) -> Option<u32> {
+        active_borrow_location(weak).map(Location::line)
+    }
+
+    #[test]
+    fn location() {
+        let mut rc = Rc::new(0);
+        let weak = Rc::downgrade(&rc);
+        assert_eq!(line(&weak), None);
+
+        let mutable = Rc::borrow_mut(&mut rc);
+        let borrowed = line!() - 1;
+        assert_eq!(line(&weak), Some(borrowed));
+        assert_eq!(active_borrow_location(&weak).unwrap().file(), file!());
+        let mapped = BorrowRefMut::map(mutable, |n| n);
+        assert_eq!(line(&weak), Some(borrowed));
+        drop(mapped);
+        assert_eq!(line(&weak), None);
+
+        Rc::with_borrow_mut(&mut rc, |_| {
+            assert_eq!(line(&weak), Some(line!() - 1));
+        });
+        assert_eq!(line(&Weak::<u8>::new()), None);
+    }
+
+    #[test]
+    fn many() {
+        let (mut a, mut b) = (Rc::new(0), Rc::new(1));
+        let (weak_a, weak_b) = (Rc::downgrade(&a), Rc::downgrade(&b));
+
+        let borrows = borrow_mut_many([&mut a, &mut b]).unwrap();
+        let borrowed = line!() - 1;
+        assert_eq!(line(&weak_a), Some(borrowed));
+        assert_eq!(line(&weak_b), Some(borrowed));
+        assert_eq!(active_borrow_location(&weak_a).unwrap().file(), file!());
+        drop(borrows);
+
+        let borrows = borrow_mut_iter([&mut a, &mut b]).unwrap();
+        assert_eq!(line(&weak_b), Some(line!() - 1));
+        drop(borrows);
+        assert_eq!(line(&weak_a), None);
+    }
+
+    /// Asserts that `borrow` panics, naming where the contents of `weak` are borrowed.
+    fn assert_panic_names_location<T: ?Sized>(weak: Weak<T>, borrow: impl FnOnce()) {
+        let location = active_borrow_location(&weak).unwrap();
+        let payload = catch_unwind(AssertUnwindSafe(borrow)).unwrap_err();
+        let message = payload.downcast::<String>().unwrap();
+        assert_eq!(
+            *message,
+            format!("cannot borrow mutably, already borrowed, at {location}")
+        );
+    }
+
+    #[test]
+    fn panic_message() {
+        let mut rc = Rc::new(0);
+        core::mem::forget(Rc::borrow_mut(&mut rc));
+        assert_panic_names_location(Rc::downgrade(&rc), || {
+            Rc::borrow_mut(&mut rc);
+        });
+        leak(rc);
+
+        let rc = Rc::new(0);
+        let weak = Rc::downgrade(&rc);
+        let mut rc = Pin::new(rc);
+        core::mem::forget(Pin::borrow_mut(&mut rc));
+        assert_panic_names_location(weak, || {
+            Pin::borrow_mut(&mut rc);
+        });
+        leak(Pin::into_inner(rc));
+
+        let mut rc = Rc::new(Poison::new(0));
+        core::mem::forget(Poison::borrow_mut(&mut rc));
+        assert_panic_names_location(Rc::downgrade(&rc), || {
+            Poison::borrow_mut(&mut rc);
+        });
+        leak(rc);
+    }
+}
@@ -0,0 +1,374 @@
+//! Trees whose nodes own their children, and refer to their parent with a `Weak` reference,
+//! mutated with [`RcBorrowMut`] instead of `RefCell`.
+
+use crate::{BorrowMutError, BorrowRefMut, ChunksMut, RcBorrowMut};
+use alloc::rc::{Rc, Weak};
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+struct NodeData<T> {
+    value: T,
+    parent: Weak<NodeData<T>>,
+    children: Vec<Node<T>>,
+}
+
+/// A node of a tree, which owns its children.
+///
+/// A node can only be mutated while it's the only strong reference to its contents, so
+/// mutation panics while clones of it (e.g. from [`Node::parent`]) exist. Children are reached
+/// mutably through [`NodeMut`] handles, during which [`Node::parent`] returns `None`.
+///
+/// A node is invariant in `T`, since its clones (e.g. from [`Node::parent`]) would otherwise see
+/// values that don't live long enough for their type:
+///
+/// ```compile_fail,E0597
+/// use rc_borrow_mut::graph::Node;
+///
+/// let root: Node<&'static str> = Node::new("static");
+/// let short_lived = String::from("short-lived");
+/// let mut root: Node<&str> = root;
+/// root.with_mut(|value| *value = &short_lived);
+/// ```
+pub struct Node<T> {
+    rc: Rc<NodeData<T>>,
+    _invariant: PhantomData<fn(T) -> T>,
+}
+
+impl<T> Node<T> {
+    /// Makes a root node without children.
+    pub fn new(value: T) -> Self {
+        Self::with_parent(value, Weak::new())
+    }
+
+    fn with_parent(value: T, parent: Weak<NodeData<T>>) -> Self {
+        Self::from_rc(Rc::new(NodeData {
+            value,
+            parent,
+            children: Vec::new(),
+        }))
+    }
+
+    fn from_rc(rc: Rc<NodeData<T>>) -> Self {
+        Self {
+            rc,
+            _invariant: PhantomData,
+        }
+    }
+
+    pub fn value(&self) -> &T {
+        &self.rc.value
+    }
+
+    /// Mutably borrows the value for the duration of `f`.
+    ///
+    /// # Panics
+    ///
+    /// If there are clones of this node.
+    #[track_caller]
+    pub fn with_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        Rc::with_borrow_mut(&mut self.rc, |data| f(&mut data.value))
+    }
+
+    /// Mutably borrows the value for the duration of `f`.
+    ///
+    /// Succeeds if there are no clones of this node.
+    #[track_caller]
+    pub fn try_with_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Result<R, BorrowMutError> {
+        Rc::try_with_borrow_mut(&mut self.rc, |data| f(&mut data.value))
+    }
+
+    /// Returns the parent, unless this is a root, or the parent is mutably borrowed.
+    pub fn parent(&self) -> Option<Self> {
+        self.rc.parent.upgrade().map(Self::from_rc)
+    }
+
+    pub fn children(&self) -> &[Self] {
+        &self.rc.children
+    }
+
+    /// Returns an iterator over the children, so they can be mutated in turn.
+    ///
+    /// # Panics
+    ///
+    /// If there are clones of this node.
+    #[track_caller]
+    pub fn children_mut(&mut self) -> ChildrenMut<'_, T> {
+        let children = BorrowRefMut::map(Rc::borrow_mut(&mut self.rc), |data| &mut *data.children);
+        ChildrenMut {
+            chunks: BorrowRefMut::chunks_mut(children, 1),
+        }
+    }
+
+    /// Returns the child at `index`, so it can be mutated in turn.
+    ///
+    /// # Panics
+    ///
+    /// If there are clones of this node.
+    #[track_caller]
+    pub fn child_mut(&mut self, index: usize) -> Option<NodeMut<'_, T>> {
+        let data = Rc::borrow_mut(&mut self.rc);
+        let node = BorrowRefMut::filter_map(data, |data| data.children.get_mut(index)).ok()?;
+        Some(NodeMut { node })
+    }
+
+    /// Appends a child, returning it, so it can be mutated in turn.
+    ///
+    /// # Panics
+    ///
+    /// If there are clones of this node.
+    #[track_caller]
+    pub fn add_child(&mut self, value: T) -> NodeMut<'_, T> {
+        let child = Self::with_parent(value, Rc::downgrade(&self.rc));
+        let node = BorrowRefMut::map(Rc::borrow_mut(&mut self.rc), |data| {
+            data.children.push(child);
+            data.children.last_mut().unwrap()
+        });
+        NodeMut { node }
+    }
+
+    /// Removes the child at `index`, returning it as a root.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds, or there are clones of this node or the child.
+    #[track_caller]
+    pub fn detach(&mut self, index: usize) -> Self {
+        let mut data = Rc::borrow_mut(&mut self.rc);
+        // Clears the parent before removing the child, so that if this panics, the tree is intact.
+        Rc::borrow_mut(&mut data.children[index].rc).parent = Weak::new();
+        data.children.remove(index)
+    }
+
+    /// Returns an iterator over this node and its descendants, visiting parents before their
+    /// children.
+    pub fn depth_first(&self) -> DepthFirst<'_, T> {
+        DepthFirst {
+            stack: alloc::vec![self],
+        }
+    }
+}
+
+/// Makes another strong reference to the same node, which prevents mutating it until dropped.
+impl<T> Clone for Node<T> {
+    fn clone(&self) -> Self {
+        Self::from_rc(Rc::clone(&self.rc))
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Node<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Node")
+            .field("value", self.value())
+            .field("children", &self.children())
+            .finish()
+    }
+}
+
+/// A mutable handle to a child, which keeps its parent borrowed.
+///
+/// Unlike `&mut Node<T>`, it can't be used to replace the child, e.g. with `mem::swap`, which
+/// would leave it with the wrong parent:
+///
+/// ```compile_fail
+/// use rc_borrow_mut::graph::Node;
+///
+/// let (mut a, mut b) = (Node::new(0), Node::new(1));
+/// let (mut a_child, mut b_child) = (a.add_child(2), b.add_child(3));
+/// core::mem::swap(&mut *a_child, &mut *b_child);
+/// ```
+pub struct NodeMut<'a, T> {
+    node: BorrowRefMut<'a, Node<T>>,
+}
+
+impl<T> NodeMut<'_, T> {
+    /// Like [`Node::with_mut`].
+    #[track_caller]
+    pub fn with_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.node.with_mut(f)
+    }
+
+    /// Like [`Node::try_with_mut`].
+    #[track_caller]
+    pub fn try_with_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Result<R, BorrowMutError> {
+        self.node.try_with_mut(f)
+    }
+
+    /// Like [`Node::children_mut`].
+    #[track_caller]
+    pub fn children_mut(&mut self) -> ChildrenMut<'_, T> {
+        self.node.children_mut()
+    }
+
+    /// Like [`Node::child_mut`].
+    #[track_caller]
+    pub fn child_mut(&mut self, index: usize) -> Option<NodeMut<'_, T>> {
+        self.node.child_mut(index)
+    }
+
+    /// Like [`Node::add_child`].
+    #[track_caller]
+    pub fn add_child(&mut self, value: T) -> NodeMut<'_, T> {
+        self.node.add_child(value)
+    }
+
+    /// Like [`Node::detach`].
+    #[track_caller]
+    pub fn detach(&mut self, index: usize) -> Node<T> {
+        self.node.detach(index)
+    }
+}
+
+impl<T> Deref for NodeMut<'_, T> {
+    type Target = Node<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.node
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for NodeMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// An iterator over mutable handles to the children of a node, returned by
+/// [`Node::children_mut`].
+pub struct ChildrenMut<'a, T> {
+    chunks: ChunksMut<'a, Node<T>>,
+}
+
+impl<'a, T> Iterator for ChildrenMut<'a, T> {
+    type Item = NodeMut<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.chunks.next()?;
+        Some(NodeMut {
+            node: BorrowRefMut::map(chunk, |chunk| &mut chunk[0]),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for ChildrenMut<'_, T> {}
+
+impl<T> FusedIterator for ChildrenMut<'_, T> {}
+
+/// A depth-first iterator over a tree, returned by [`Node::depth_first`].
+pub struct DepthFirst<'a, T> {
+    /// Nodes to visit, the next one last.
+    stack: Vec<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for DepthFirst<'a, T> {
+    type Item = &'a Node<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children().iter().rev());
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Node;
+    use crate::BorrowMutError;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::vec::Vec;
+
+    fn values(root: &Node<u8>) -> Vec<u8> {
+        root.depth_first().map(|node| *node.value()).collect()
+    }
+
+    #[test]
+    fn tree() {
+        let mut root = Node::new(0);
+        let mut child = root.add_child(1);
+        child.add_child(2).with_mut(|value| *value += 1);
+        child.add_child(4);
+        drop(child);
+        root.add_child(5);
+        assert_eq!(values(&root), [0, 1, 3, 4, 5]);
+
+        for mut child in root.children_mut() {
+            child.with_mut(|value| *value *= 10);
+        }
+        assert_eq!(values(&root), [0, 10, 3, 4, 50]);
+    }
+
+    #[test]
+    fn parent() {
+        let mut root = Node::new(0);
+        root.add_child(1).add_child(2);
+        let grandchild = &root.children()[0].children()[0];
+        let child = grandchild.parent().unwrap();
+        assert_eq!(*child.value(), 1);
+        assert_eq!(*child.parent().unwrap().value(), 0);
+        assert!(root.parent().is_none());
+
+        // The child is cloned, so it can't be mutated.
+        assert!(matches!(
+            root.child_mut(0).unwrap().try_with_mut(|_| ()),
+            Err(BorrowMutError::OtherStrongReferencesExist { .. })
+        ));
+        drop(child);
+        root.child_mut(0).unwrap().with_mut(|value| *value = 3);
+        assert!(root.child_mut(1).is_none());
+
+        // The parent is borrowed while its children are.
+        let mut children = root.children_mut();
+        assert_eq!(children.len(), 1);
+        assert!(children.next().unwrap().parent().is_none());
+        drop(children);
+        assert!(root.children()[0].parent().is_some());
+    }
+
+    #[test]
+    fn detach() {
+        let mut root = Node::new(0);
+        root.add_child(1).add_child(2);
+        root.add_child(3);
+        let mut child = root.detach(0);
+        assert!(child.parent().is_none());
+        assert_eq!(values(&root), [0, 3]);
+        assert_eq!(values(&child), [1, 2]);
+        child.with_mut(|value| *value = 4);
+        assert_eq!(*child.children()[0].parent().unwrap().value(), 4);
+    }
+
+    #[test]
+    fn detach_cloned() {
+        let mut root = Node::new(0);
+        root.add_child(1);
+        let clone = root.children()[0].clone();
+        assert!(catch_unwind(AssertUnwindSafe(|| root.detach(0))).is_err());
+        assert_eq!(values(&root), [0, 1]);
+        assert_eq!(*clone.parent().unwrap().value(), 0);
+        drop(clone);
+        assert!(root.detach(0).parent().is_none());
+    }
+
+    #[test]
+    fn nested() {
+        let mut root = Node::new(0);
+        let mut child = root.add_child(1);
+        child.add_child(2);
+        let mut grandchild = child.child_mut(0).unwrap();
+        grandchild.add_child(3);
+        assert_eq!(*grandchild.value(), 2);
+        drop(grandchild);
+        let detached = child.detach(0);
+        assert_eq!(values(&detached), [2, 3]);
+        assert!(detached.parent().is_none());
+        drop(child);
+        assert_eq!(values(&root), [0, 1]);
+        assert_eq!(*root.children()[0].parent().unwrap().value(), 0);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::BorrowRefMut;
+use core::borrow::{Borrow, BorrowMut};
+use core::cmp::Ordering;
+use core::fmt;
+use core::future::Future;
+use core::hash::{Hash, Hasher};
+use core::iter::FusedIterator;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+#[cfg(feature = "std")]
+use std::io;
+
+// Traits forwarded to the borrowed value, mirroring those `Box` forwards.
+
+impl<T: ?Sized> AsRef<T> for BorrowRefMut<'_, T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> AsMut<T> for BorrowRefMut<'_, T> {
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T: ?Sized> Borrow<T> for BorrowRefMut<'_, T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> BorrowMut<T> for BorrowRefMut<'_, T> {
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T: ?Sized + PartialEq> PartialEq for BorrowRefMut<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for BorrowRefMut<'_, T> {}
+
+impl<T: ?Sized + PartialOrd> PartialOrd for BorrowRefMut<'_, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Ord> Ord for BorrowRefMut<'_, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Hash> Hash for BorrowRefMut<'_, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+impl<T: ?Sized> fmt::Pointer for BorrowRefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.value, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Write> fmt::Write for BorrowRefMut<'_, T> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (**self).write_str(s)
+    }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        (**self).write_char(c)
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
+        (**self).write_fmt(args)
+    }
+}
+
+impl<I: ?Sized + Iterator> Iterator for BorrowRefMut<'_, I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        (**self).next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (**self).size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<I::Item> {
+        (**self).nth(n)
+    }
+}
+
+impl<I: ?Sized + DoubleEndedIterator> DoubleEndedIterator for BorrowRefMut<'_, I> {
+    fn next_back(&mut self) -> Option<I::Item> {
+        (**self).next_back()
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<I::Item> {
+        (**self).nth_back(n)
+    }
+}
+
+impl<I: ?Sized + ExactSizeIterator> ExactSizeIterator for BorrowRefMut<'_, I> {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+}
+
+impl<I: ?Sized + FusedIterator> FusedIterator for BorrowRefMut<'_, I> {}
+
+impl<F: ?Sized + Future + Unpin> Future for BorrowRefMut<'_, F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        Pin::new(&mut **self).poll(cx)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: ?Sized + io::Read> io::Read for BorrowRefMut<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        (**self).read_vectored(bufs)
+    }
+
+    fn read_to_end(&mut self, buf: &mut std::vec::Vec<u8>) -> io::Result<usize> {
+        (**self).read_to_end(buf)
+    }
+
+    fn read_to_string(&mut self, buf: &mut std::string::String) -> io::Result<usize> {
+        (**self).read_to_string(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_exact(buf)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: ?Sized + io::Write> io::Write for BorrowRefMut<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        (**self).write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (**self).write_all(buf)
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> io::Result<()> {
+        (**self).write_fmt(args)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: ?Sized + io::Seek> io::Seek for BorrowRefMut<'_, S> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        (**self).seek(pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        (**self).stream_position()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<B: ?Sized + io::BufRead> io::BufRead for BorrowRefMut<'_, B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        (**self).fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        (**self).consume(amt);
+    }
+
+    fn read_until(&mut self, byte: u8, buf: &mut std::vec::Vec<u8>) -> io::Result<usize> {
+        (**self).read_until(byte, buf)
+    }
+
+    fn read_line(&mut self, buf: &mut std::string::String) -> io::Result<usize> {
+        (**self).read_line(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BorrowRefMut, RcBorrowMut};
+    use core::borrow::Borrow;
+    use core::fmt::Write as _;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::rc::Rc;
+    use std::string::String;
+    use std::vec::Vec;
+    use std::{format, vec};
+
+    fn hash(value: &impl Hash) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn compare() {
+        let (mut a, mut b) = (Rc::new(1), Rc::new(2));
+        let (a, b) = (Rc::borrow_mut(&mut a), Rc::borrow_mut(&mut b));
+        assert!(a < b);
+        assert_ne!(a, b);
+        assert_eq!(a.cmp(&b), core::cmp::Ordering::Less);
+        assert_eq!(hash(&a), hash(&1));
+        let value: &i32 = a.borrow();
+        assert_eq!(value, a.as_ref());
+        assert_eq!(format!("{a:p}"), format!("{:p}", value));
+    }
+
+    #[test]
+    fn fmt_write() {
+        let mut rc = Rc::new(String::new());
+        let mut mutable = Rc::borrow_mut(&mut rc);
+        write!(mutable, "{}", 1).unwrap();
+        mutable.write_char('2').unwrap();
+        drop(mutable);
+        assert_eq!(*rc, "12");
+    }
+
+    #[test]
+    fn iterator() {
+        let mut rc = Rc::new(vec![1, 2, 3, 4].into_iter());
+        let mut mutable = Rc::borrow_mut(&mut rc);
+        assert_eq!(mutable.len(), 4);
+        assert_eq!(mutable.next_back(), Some(4));
+        assert_eq!(mutable.by_ref().take(2).collect::<Vec<_>>(), [1, 2]);
+        drop(mutable);
+        assert_eq!(rc.as_slice(), [3]);
+    }
+
+    #[test]
+    fn future() {
+        let mut rc = Rc::new(core::future::ready(1));
+        let mutable = pin!(Rc::borrow_mut(&mut rc));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(mutable.poll(&mut cx), Poll::Ready(1));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io() {
+        use std::io::{BufRead, Cursor, Read, Seek, SeekFrom, Write};
+
+        let mut rc = Rc::new(Cursor::new(Vec::new()));
+        let mut mutable = Rc::borrow_mut(&mut rc);
+        mutable.write_all(b"one\ntwo").unwrap();
+        assert_eq!(mutable.seek(SeekFrom::Start(0)).unwrap(), 0);
+        let mut line = String::new();
+        mutable.read_line(&mut line).unwrap();
+        assert_eq!(line, "one\n");
+        let mut rest = String::new();
+        mutable.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "two");
+        drop(mutable);
+        assert_eq!(rc.get_ref(), b"one\ntwo");
+    }
+
+    #[test]
+    fn generic() {
+        fn sum(values: impl IntoIterator<Item = u8>) -> u8 {
+            values.into_iter().sum()
+        }
+
+        let mut rc = Rc::new([1, 2, 3].into_iter());
+        let mutable = Rc::borrow_mut(&mut rc);
+        assert_eq!(sum(BorrowRefMut::map(mutable, |i| i)), 6);
+    }
+}
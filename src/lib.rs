@@ -1,12 +1,46 @@
-#![feature(alloc_layout_extra)]
-#![feature(pointer_byte_offsets)]
-#![feature(layout_for_ptr)]
-#![feature(cell_update)]
+#![no_std]
+#![cfg_attr(feature = "nightly", feature(allocator_api, layout_for_ptr))]
 
-use std::fmt;
-use std::fmt::{Debug, Formatter};
-use std::ops::{Deref, DerefMut};
-use std::rc::Rc;
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+use alloc::rc::Rc;
+use core::any::Any;
+#[cfg(feature = "std")]
+use core::cell::Cell;
+use core::fmt;
+use core::fmt::Formatter;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+#[cfg(target_has_atomic = "ptr")]
+mod arc;
+#[cfg(feature = "debug-borrows")]
+mod debug;
+pub mod graph;
+mod impls;
+mod many;
+mod owned;
+mod pin;
+#[cfg(feature = "std")]
+mod poison;
+mod slice;
+mod weak;
+
+#[cfg(target_has_atomic = "ptr")]
+pub use arc::{ArcBorrowMut, ArcBorrowRefMut};
+#[cfg(feature = "debug-borrows")]
+pub use debug::active_borrow_location;
+pub use many::{borrow_mut_iter, borrow_mut_many, ManyBorrowError};
+pub use owned::OwnedBorrowMut;
+pub use pin::{poll_rc, PinRcBorrowMut};
+#[cfg(feature = "std")]
+pub use poison::Poison;
+pub use slice::ChunksMut;
+pub use weak::{UpgradeState, WeakBorrowExt};
 
 pub trait RcBorrowMut<T: ?Sized> {
     /// Mutably borrows the contents of the Rc.
@@ -14,29 +48,266 @@ pub trait RcBorrowMut<T: ?Sized> {
     /// # Panics
     ///
     /// If there are other strong references.
-    fn borrow_mut(me: &mut Self) -> BorrowRefMut<T> {
+    #[track_caller]
+    fn borrow_mut(me: &mut Self) -> BorrowRefMut<'_, T> {
         Self::try_borrow_mut(me).unwrap()
     }
 
     /// Mutably borrows the contents of the Rc.
     ///
+    /// Succeeds if the argument is the only strong reference, and the layout of `Rc`
+    /// (checked once) is as expected.
+    #[track_caller]
+    fn try_borrow_mut(me: &mut Self) -> Result<BorrowRefMut<'_, T>, BorrowMutError>;
+
+    /// Mutably borrows the contents of the Rc for the duration of `f`.
+    ///
+    /// Unlike [`RcBorrowMut::borrow_mut`], the strong count is always restored
+    /// before this returns (or unwinds), since the guard is never exposed.
+    ///
+    /// # Panics
+    ///
+    /// If there are other strong references.
+    #[track_caller]
+    fn with_borrow_mut<R>(me: &mut Self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut mutable = Self::borrow_mut(me);
+        f(&mut mutable)
+    }
+
+    /// Mutably borrows the contents of the Rc for the duration of `f`.
+    ///
     /// Succeeds if the argument is the only strong reference.
-    fn try_borrow_mut(me: &mut Self) -> Result<BorrowRefMut<T>, OtherStrongReferencesExist>;
+    #[track_caller]
+    fn try_with_borrow_mut<R>(
+        me: &mut Self,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> Result<R, BorrowMutError> {
+        let mut mutable = Self::try_borrow_mut(me)?;
+        Ok(f(&mut mutable))
+    }
+
+    /// Mutably borrows the contents of the Rc, taking ownership of it.
+    ///
+    /// Succeeds if the argument is the only strong reference, otherwise it is returned.
+    #[track_caller]
+    fn into_borrow_mut(me: Self) -> Result<OwnedBorrowMut<T, Self>, Self>
+    where
+        Self: Sized;
+
+    /// Mutably borrows the contents of the Rc, first cloning them into a new Rc if there are
+    /// other strong references.
+    ///
+    /// Unlike `Rc::make_mut`, `Weak` references stay associated with the contents if the
+    /// argument is the only strong reference.
+    ///
+    /// # Panics
+    ///
+    /// If the contents are already borrowed, or the layout of `Rc` isn't as expected.
+    #[track_caller]
+    fn make_mut_keep_weaks(me: &mut Self) -> BorrowRefMut<'_, T>
+    where
+        T: Clone;
 }
 
-pub struct OtherStrongReferencesExist;
+/// The reason mutably borrowing the contents of an `Rc` or `Arc` failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BorrowMutError {
+    /// The argument isn't the only strong reference.
+    OtherStrongReferencesExist {
+        strong_count: usize,
+        weak_count: usize,
+    },
+    /// The standard library's reference counted allocations aren't laid out the way this crate
+    /// expects, so borrowing would corrupt memory.
+    LayoutMismatch,
+    /// The strong count is zero, because the contents are already borrowed by a leaked guard.
+    AlreadyBorrowed,
+    /// A previous borrow ended during a panic, so the contents may be invalid.
+    Poisoned,
+}
 
-impl Debug for OtherStrongReferencesExist {
+impl fmt::Display for BorrowMutError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.write_str("Cannot borrow mutably, other strong references exist.")
+        match self {
+            Self::OtherStrongReferencesExist {
+                strong_count,
+                weak_count,
+            } => write!(
+                f,
+                "cannot borrow mutably, other strong references exist \
+                (strong count: {strong_count}, weak count: {weak_count})"
+            ),
+            Self::LayoutMismatch => {
+                f.write_str("cannot borrow mutably, std's reference count layout changed")
+            }
+            Self::AlreadyBorrowed => f.write_str("cannot borrow mutably, already borrowed"),
+            Self::Poisoned => f.write_str("cannot borrow mutably, poisoned by a panic"),
+        }
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for BorrowMutError {}
+
 /// A mutable handle to the contents of an `Rc`.
+///
+/// While it exists, the strong count is zero and the weak count is raised, so that forgetting
+/// the handle leaks the allocation instead of freeing it.
+///
+/// The `Rc` stays mutably borrowed until the handle is dropped, not just until its last use:
+///
+/// ```compile_fail,E0502
+/// use rc_borrow_mut::RcBorrowMut;
+/// use std::rc::Rc;
+///
+/// let mut rc = Rc::new(0);
+/// let mut mutable = Rc::borrow_mut(&mut rc);
+/// *mutable += 1;
+/// let clone = Rc::clone(&rc);
+/// ```
 pub struct BorrowRefMut<'a, T: ?Sized> {
-    inner: &'a mut Rc<T>,
+    value: NonNull<T>,
+    restore: RestoreHandle,
+    // Only dropped.
+    #[allow(dead_code)]
+    marker: BorrowMarker<'a, T>,
+}
+
+/// Ties a `BorrowRefMut` to the `&mut Rc` it borrowed until it's dropped. Without a `Drop` impl
+/// mentioning the lifetime, the `Rc` would be usable again after the handle's last use, but
+/// before it's dropped and the strong count is restored.
+struct BorrowMarker<'a, T: ?Sized>(PhantomData<&'a mut T>);
+
+impl<T: ?Sized> Drop for BorrowMarker<'_, T> {
+    fn drop(&mut self) {}
+}
+
+impl<'a, T: ?Sized> BorrowRefMut<'a, T> {
+    /// Makes a new `BorrowRefMut` for a component of the borrowed data, e.g. a field.
+    ///
+    /// The strong count of the original `Rc` is restored when the returned handle is dropped.
+    ///
+    /// This is an associated function that needs to be used as `BorrowRefMut::map(...)`, so as
+    /// not to interfere with methods of the same name on the contents.
+    pub fn map<U: ?Sized, F>(orig: Self, f: F) -> BorrowRefMut<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        // If `f` panics, `orig` is dropped, restoring the strong count.
+        let value = NonNull::from(f(unsafe { &mut *orig.value.as_ptr() }));
+        BorrowRefMut {
+            value,
+            restore: orig.restore,
+            marker: BorrowMarker(PhantomData),
+        }
+    }
+
+    /// Makes a new `BorrowRefMut` for an optional component of the borrowed data. The original
+    /// handle is returned as an `Err(..)` if the closure returns `None`.
+    ///
+    /// This is an associated function that needs to be used as `BorrowRefMut::filter_map(...)`,
+    /// so as not to interfere with methods of the same name on the contents.
+    pub fn filter_map<U: ?Sized, F>(orig: Self, f: F) -> Result<BorrowRefMut<'a, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        match f(unsafe { &mut *orig.value.as_ptr() }) {
+            Some(value) => Ok(BorrowRefMut {
+                value: NonNull::from(value),
+                restore: orig.restore,
+                marker: BorrowMarker(PhantomData),
+            }),
+            None => Err(orig),
+        }
+    }
+
+    /// Makes the borrow set the flag found via `flag` if it ends during a panic.
+    ///
+    /// # Safety
+    ///
+    /// `flag` must return a pointer to a `Cell` inside the borrowed contents, which is not
+    /// otherwise mutably accessible through this handle.
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn poison_on_panic(&mut self, flag: impl FnOnce(*mut T) -> *mut Cell<bool>) {
+        let RestoreHandle::Unique(restore) = &mut self.restore else {
+            unreachable!("only fresh borrows can be poisoned");
+        };
+        restore.poison = Some(NonNull::new_unchecked(flag(self.value.as_ptr())));
+    }
+
+    /// Splits a `BorrowRefMut` into multiple `BorrowRefMut`s for different components of the
+    /// borrowed data.
+    ///
+    /// The strong count of the original `Rc` is restored when both returned handles have been
+    /// dropped.
+    ///
+    /// This is an associated function that needs to be used as `BorrowRefMut::map_split(...)`,
+    /// so as not to interfere with methods of the same name on the contents.
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(
+        orig: Self,
+        f: F,
+    ) -> (BorrowRefMut<'a, U>, BorrowRefMut<'a, V>)
+    where
+        F: FnOnce(&mut T) -> (&mut U, &mut V),
+    {
+        let (a, b) = f(unsafe { &mut *orig.value.as_ptr() });
+        let (a, b) = (NonNull::from(a), NonNull::from(b));
+        let (restore_a, restore_b) = orig.restore.split();
+        (
+            BorrowRefMut {
+                value: a,
+                restore: restore_a,
+                marker: BorrowMarker(PhantomData),
+            },
+            BorrowRefMut {
+                value: b,
+                restore: restore_b,
+                marker: BorrowMarker(PhantomData),
+            },
+        )
+    }
 }
 
+impl<T> BorrowRefMut<'_, T> {
+    /// Replaces the borrowed value, returning the old one. `Weak` references keep pointing at the
+    /// same allocation.
+    ///
+    /// This is an associated function that needs to be used as `BorrowRefMut::replace(...)`, so
+    /// as not to interfere with methods of the same name on the contents.
+    pub fn replace(me: &mut Self, value: T) -> T {
+        mem::replace(&mut **me, value)
+    }
+
+    /// Takes the borrowed value, leaving `T::default()` in its place. `Weak` references keep
+    /// pointing at the same allocation.
+    ///
+    /// This is an associated function that needs to be used as `BorrowRefMut::take(...)`, so as
+    /// not to interfere with methods of the same name on the contents.
+    pub fn take(me: &mut Self) -> T
+    where
+        T: Default,
+    {
+        mem::take(&mut **me)
+    }
+}
+
+macro_rules! impl_downcast {
+    ($($any:ty),*) => {$(
+        impl<'a> BorrowRefMut<'a, $any> {
+            /// Attempts to downcast the borrowed value to a concrete type. The original handle is
+            /// returned as an `Err(..)` if the value is of a different type.
+            ///
+            /// Like `Box::downcast`, this is a method, since `Any` has none of the same name.
+            pub fn downcast<U: Any>(self) -> Result<BorrowRefMut<'a, U>, Self> {
+                BorrowRefMut::filter_map(self, |value| value.downcast_mut())
+            }
+        }
+    )*};
+}
+
+impl_downcast!(dyn Any, dyn Any + Send, dyn Any + Send + Sync);
+
 impl<T: ?Sized + fmt::Debug> fmt::Debug for BorrowRefMut<'_, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         (**self).fmt(f)
@@ -53,63 +324,229 @@ impl<T: ?Sized> Deref for BorrowRefMut<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        let raw = Rc::as_ptr(&self.inner);
-        unsafe { &*raw }
+        unsafe { self.value.as_ref() }
     }
 }
 
 impl<T: ?Sized> DerefMut for BorrowRefMut<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        let raw = Rc::as_ptr(&self.inner);
-        unsafe { &mut *(raw as *mut T) }
+        unsafe { self.value.as_mut() }
     }
 }
 
-impl<T: ?Sized> Drop for BorrowRefMut<'_, T> {
+/// Restores the counts of a borrowed `Rc` when dropped.
+struct Restore {
+    rc_box: NonNull<hack::RcBox<()>>,
+    /// Set if the borrow ends during a panic.
+    #[cfg(feature = "std")]
+    poison: Option<NonNull<Cell<bool>>>,
+    /// The address of the value, under which the location of the borrow is recorded.
+    #[cfg(feature = "debug-borrows")]
+    value: usize,
+}
+
+impl Restore {
+    /// Borrows the contents of an `Rc`, returning a handle to restore its counts.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must point to the value of an `Rc` that passed [`check_borrow_mut`].
+    #[track_caller]
+    unsafe fn borrow<T: ?Sized>(raw: *const T) -> Self {
+        // Only the counts are accessed from now on, so the value's type is irrelevant.
+        let rc_box = hack::raw_to_rc_box(raw) as *mut hack::RcBox<()>;
+        (*rc_box).strong.update(|x| {
+            debug_assert_eq!(x, 1);
+            x - 1
+        });
+        (*rc_box).weak.update(|x| x + hack::BORROWED_WEAK);
+        #[cfg(feature = "debug-borrows")]
+        debug::record(raw.cast::<()>().addr(), core::panic::Location::caller());
+        Self {
+            rc_box: NonNull::new_unchecked(rc_box),
+            #[cfg(feature = "std")]
+            poison: None,
+            #[cfg(feature = "debug-borrows")]
+            value: raw.cast::<()>().addr(),
+        }
+    }
+}
+
+impl Drop for Restore {
     fn drop(&mut self) {
-        let raw = Rc::as_ptr(&self.inner);
         unsafe {
-            let rc_box = hack::raw_to_rc_box(raw);
-            (&*rc_box).strong.update(|x| {
+            #[cfg(feature = "std")]
+            if let Some(poison) = self.poison.filter(|_| std::thread::panicking()) {
+                poison.as_ref().set(true);
+            }
+            #[cfg(feature = "debug-borrows")]
+            debug::remove(self.value);
+
+            let rc_box = self.rc_box.as_ptr();
+            (*rc_box).strong.update(|x| {
                 debug_assert_eq!(x, 0);
                 x + 1
             });
+            (*rc_box).weak.update(|x| {
+                debug_assert!(x > hack::BORROWED_WEAK);
+                x - hack::BORROWED_WEAK
+            });
         }
     }
 }
 
-impl<T: ?Sized> RcBorrowMut<T> for Rc<T> {
-    fn try_borrow_mut(me: &mut Self) -> Result<BorrowRefMut<T>, OtherStrongReferencesExist> {
-        debug_assert_ne!(Rc::strong_count(me), 0);
-        if Rc::strong_count(me) > 1 {
-            return Err(OtherStrongReferencesExist);
+/// Restores the counts of a borrowed `Rc` once every handle to it is dropped.
+enum RestoreHandle {
+    Unique(Restore),
+    Shared(Rc<Restore>),
+}
+
+impl RestoreHandle {
+    fn split(self) -> (Self, Self) {
+        let shared = match self {
+            Self::Unique(restore) => Rc::new(restore),
+            Self::Shared(shared) => shared,
+        };
+        (Self::Shared(Rc::clone(&shared)), Self::Shared(shared))
+    }
+}
+
+/// Implements `RcBorrowMut` for `Rc`, which is generic over its allocator with the `nightly`
+/// feature. `Rc`s with allocators that aren't `Clone` can't have `Weak` references, so there's
+/// no reason to borrow them.
+macro_rules! impl_rc_borrow_mut {
+    ($($item:item)*) => {
+        #[cfg(not(feature = "nightly"))]
+        impl<T: ?Sized> RcBorrowMut<T> for Rc<T> {
+            $($item)*
         }
 
+        #[cfg(feature = "nightly")]
+        impl<T: ?Sized, A: core::alloc::Allocator + Clone> RcBorrowMut<T> for Rc<T, A> {
+            $($item)*
+        }
+    };
+}
+
+impl_rc_borrow_mut! {
+    #[track_caller]
+    fn borrow_mut(me: &mut Self) -> BorrowRefMut<'_, T> {
+        let value = Rc::as_ptr(me).cast::<()>();
+        unwrap_borrow(Self::try_borrow_mut(me), value)
+    }
+
+    #[track_caller]
+    fn try_borrow_mut(me: &mut Self) -> Result<BorrowRefMut<'_, T>, BorrowMutError> {
+        check_borrow_mut(Rc::strong_count(me), || Rc::weak_count(me))?;
+
         unsafe {
             let raw = Rc::as_ptr(me);
-            let rc_box = hack::raw_to_rc_box(raw);
-            (&*rc_box).strong.update(|x| {
-                debug_assert_eq!(x, 1);
-                x - 1
-            });
+            Ok(BorrowRefMut {
+                value: NonNull::new_unchecked(raw as *mut T),
+                restore: RestoreHandle::Unique(Restore::borrow(raw)),
+                marker: BorrowMarker(PhantomData),
+            })
+        }
+    }
+
+    #[track_caller]
+    fn into_borrow_mut(me: Self) -> Result<OwnedBorrowMut<T, Self>, Self> {
+        if check_borrow_mut(Rc::strong_count(&me), || Rc::weak_count(&me)).is_err() {
+            return Err(me);
+        }
+
+        unsafe {
+            let raw = Rc::as_ptr(&me);
+            Ok(OwnedBorrowMut::new(
+                NonNull::new_unchecked(raw as *mut T),
+                Restore::borrow(raw),
+                me,
+            ))
+        }
+    }
+
+    #[track_caller]
+    fn make_mut_keep_weaks(me: &mut Self) -> BorrowRefMut<'_, T>
+    where
+        T: Clone,
+    {
+        if Rc::strong_count(me) > 1 {
+            // Clones the contents into a new allocation, disassociating `Weak` references.
+            Rc::make_mut(me);
+        }
+        Self::borrow_mut(me)
+    }
+}
 
-            Ok(BorrowRefMut { inner: me })
+/// Unwraps the result of borrowing the contents of an `Rc` or `Arc`, which are at `value`. With
+/// the `debug-borrows` feature, the panic message names where they're borrowed, if they are.
+#[track_caller]
+fn unwrap_borrow<B>(result: Result<B, BorrowMutError>, value: *const ()) -> B {
+    match result {
+        Ok(mutable) => mutable,
+        Err(error) => {
+            #[cfg(feature = "debug-borrows")]
+            if let Some(location) = debug::location(value.addr()) {
+                panic!("{error}, at {location}");
+            }
+            #[cfg(not(feature = "debug-borrows"))]
+            let _ = value;
+            panic!("{error}")
         }
     }
 }
 
+/// Succeeds if the contents of an `Rc` with these counts may be mutably borrowed.
+fn check_borrow_mut(
+    strong_count: usize,
+    weak_count: impl FnOnce() -> usize,
+) -> Result<(), BorrowMutError> {
+    if !hack::rc_layout_matches() {
+        return Err(BorrowMutError::LayoutMismatch);
+    }
+    match strong_count {
+        0 => Err(BorrowMutError::AlreadyBorrowed),
+        1 => Ok(()),
+        strong_count => Err(BorrowMutError::OtherStrongReferencesExist {
+            strong_count,
+            weak_count: weak_count(),
+        }),
+    }
+}
+
 mod hack {
+    use alloc::rc::{self, Rc};
+    #[cfg(target_has_atomic = "ptr")]
+    use alloc::sync::{self as arc, Arc};
     use core::alloc::Layout;
-    use std::cell::Cell;
-    use std::mem::align_of_val_raw;
+    use core::any::Any;
+    use core::cell::Cell;
+    #[cfg(target_has_atomic = "ptr")]
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::{AtomicU8, Ordering};
 
     #[repr(C)]
     pub struct RcBox<T: ?Sized> {
         pub strong: Cell<usize>,
-        _weak: Cell<usize>,
+        pub weak: Cell<usize>,
         _value: T,
     }
 
+    #[cfg(target_has_atomic = "ptr")]
+    #[repr(C)]
+    pub struct ArcInner<T: ?Sized> {
+        pub strong: AtomicUsize,
+        pub weak: AtomicUsize,
+        _data: T,
+    }
+
+    /// Added to the weak count of a borrowed `Rc`. This keeps the allocation alive if the borrow
+    /// is leaked, and tells a borrowed value (strong count of zero) apart from a dropped one.
+    pub const BORROWED_WEAK: usize = 1 << (usize::BITS - 2);
+
+    /// # Safety
+    ///
+    /// `ptr` must point to the value of a live `Rc`, which must not be mutably borrowed.
     pub unsafe fn raw_to_rc_box<T: ?Sized>(ptr: *const T) -> *mut RcBox<T> {
         let offset = data_offset(ptr);
 
@@ -117,22 +554,153 @@ mod hack {
         ptr.byte_sub(offset) as *mut RcBox<T>
     }
 
+    /// Like [`raw_to_rc_box`], but the value may be borrowed or dropped, since its alignment
+    /// is given.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point into an `Rc` allocation, and `align` must be the alignment of its value.
+    pub unsafe fn raw_to_rc_box_align<T: ?Sized>(ptr: *const T, align: usize) -> *mut RcBox<T> {
+        ptr.byte_sub(data_offset_align(align)) as *mut RcBox<T>
+    }
+
+    #[cfg(target_has_atomic = "ptr")]
+    /// # Safety
+    ///
+    /// `ptr` must point to the value of a live `Arc`, which must not be mutably borrowed.
+    pub unsafe fn raw_to_arc_inner<T: ?Sized>(ptr: *const T) -> *mut ArcInner<T> {
+        // `ArcInner` has the same header layout as `RcBox`.
+        let offset = data_offset(ptr);
+
+        // Reverse the offset to find the original ArcInner.
+        ptr.byte_sub(offset) as *mut ArcInner<T>
+    }
+
+    #[cfg(feature = "nightly")]
     unsafe fn data_offset<T: ?Sized>(ptr: *const T) -> usize {
-        data_offset_align(align_of_val_raw(ptr))
+        data_offset_align(core::mem::align_of_val_raw(ptr))
     }
 
+    #[cfg(not(feature = "nightly"))]
+    unsafe fn data_offset<T: ?Sized>(ptr: *const T) -> usize {
+        // The value is live and not mutably borrowed, so we may reference it.
+        data_offset_align(core::mem::align_of_val(&*ptr))
+    }
+
+    /// The offset of the value from the start of an `RcBox` or `ArcInner`, given the alignment of
+    /// the value. This is the header size, rounded up to the alignment when that exceeds the
+    /// header's own.
     #[inline]
-    fn data_offset_align(align: usize) -> usize {
+    pub fn data_offset_align(align: usize) -> usize {
         let layout = Layout::new::<RcBox<()>>();
-        layout.size() + layout.padding_needed_for(align)
+        let value = Layout::from_size_align(0, align).unwrap();
+        layout.extend(value).unwrap().1
+    }
+
+    /// A zero-sized value aligned to a page, past the header, so its offset differs from the
+    /// header size.
+    #[repr(align(4096))]
+    struct OverAligned;
+
+    const UNVERIFIED: u8 = 0;
+    const MATCHES: u8 = 1;
+    const MISMATCH: u8 = 2;
+
+    /// Whether std's `RcBox` is laid out like ours, verified on the first call.
+    pub fn rc_layout_matches() -> bool {
+        static STATE: AtomicU8 = AtomicU8::new(UNVERIFIED);
+        verify_once(&STATE, || {
+            verify_rc(Rc::new(0u8))
+                && verify_rc(Rc::new(()))
+                && verify_rc::<[u64]>(Rc::new([0u64; 3]))
+                && verify_rc::<[OverAligned]>(Rc::new([]))
+                && verify_rc::<str>(Rc::from(""))
+                && verify_rc::<dyn Any>(Rc::new(OverAligned))
+        })
+    }
+
+    #[cfg(target_has_atomic = "ptr")]
+    /// Whether std's `ArcInner` is laid out like ours, verified on the first call.
+    pub fn arc_layout_matches() -> bool {
+        static STATE: AtomicU8 = AtomicU8::new(UNVERIFIED);
+        verify_once(&STATE, || {
+            verify_arc(Arc::new(0u8))
+                && verify_arc(Arc::new(()))
+                && verify_arc::<[u64]>(Arc::new([0u64; 3]))
+                && verify_arc::<[OverAligned]>(Arc::new([]))
+                && verify_arc::<str>(Arc::from(""))
+                && verify_arc::<dyn Any>(Arc::new(OverAligned))
+        })
+    }
+
+    fn verify_once(state: &AtomicU8, verify: impl FnOnce() -> bool) -> bool {
+        match state.load(Ordering::Relaxed) {
+            MATCHES => true,
+            MISMATCH => false,
+            _ => {
+                // Racing threads will all reach the same conclusion.
+                let matches = verify();
+                state.store(if matches { MATCHES } else { MISMATCH }, Ordering::Relaxed);
+                matches
+            }
+        }
+    }
+
+    /// Checks that the counts we find via `raw_to_rc_box` track those reported by std.
+    fn verify_rc<T: ?Sized>(rc: Rc<T>) -> bool {
+        let rc_box = unsafe { raw_to_rc_box(Rc::as_ptr(&rc)) };
+        let counts = |rc: &Rc<T>| unsafe {
+            (*rc_box).strong.get() == Rc::strong_count(rc)
+                && (*rc_box).weak.get() == Rc::weak_count(rc) + 1
+        };
+        let before = counts(&rc);
+        let clone = Rc::clone(&rc);
+        let cloned = counts(&rc);
+        let weak: rc::Weak<T> = Rc::downgrade(&rc);
+        let downgraded = counts(&rc);
+        drop(clone);
+        drop(weak);
+        before && cloned && downgraded && counts(&rc)
+    }
+
+    #[cfg(target_has_atomic = "ptr")]
+    /// Checks that the counts we find via `raw_to_arc_inner` track those reported by std.
+    fn verify_arc<T: ?Sized>(arc: Arc<T>) -> bool {
+        let arc_inner = unsafe { raw_to_arc_inner(Arc::as_ptr(&arc)) };
+        let counts = |arc: &Arc<T>| unsafe {
+            (*arc_inner).strong.load(Ordering::Relaxed) == Arc::strong_count(arc)
+                && (*arc_inner).weak.load(Ordering::Relaxed) == Arc::weak_count(arc) + 1
+        };
+        let before = counts(&arc);
+        let clone = Arc::clone(&arc);
+        let cloned = counts(&arc);
+        let weak: arc::Weak<T> = Arc::downgrade(&arc);
+        let downgraded = counts(&arc);
+        drop(clone);
+        drop(weak);
+        before && cloned && downgraded && counts(&arc)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::RcBorrowMut;
+    use crate::{hack, BorrowMutError, BorrowRefMut, RcBorrowMut};
+    use core::any::Any;
+    use core::sync::atomic::AtomicPtr;
     use std::cell::Cell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
     use std::rc::Rc;
+    use std::string::ToString;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+    use std::{format, vec};
+
+    /// Keeps a deliberately leaked `Rc` reachable, so Miri doesn't report it.
+    pub(crate) fn leak<T: ?Sized>(rc: Rc<T>) {
+        static LEAKED: Mutex<Vec<AtomicPtr<()>>> = Mutex::new(Vec::new());
+        let raw = Rc::into_raw(rc) as *mut ();
+        LEAKED.lock().unwrap().push(AtomicPtr::new(raw));
+    }
 
     #[test]
     fn mutate() {
@@ -167,6 +735,192 @@ mod tests {
         let _ = Rc::borrow_mut(&mut rc);
     }
 
+    #[test]
+    fn error() {
+        let mut rc = Rc::new(0);
+        let _rc2 = Rc::clone(&rc);
+        let _weak = Rc::downgrade(&rc);
+        let err = Rc::try_borrow_mut(&mut rc).unwrap_err();
+        assert_eq!(
+            err,
+            BorrowMutError::OtherStrongReferencesExist {
+                strong_count: 2,
+                weak_count: 1
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "cannot borrow mutably, other strong references exist (strong count: 2, weak count: 1)"
+        );
+        #[cfg(feature = "std")]
+        let _: &dyn std::error::Error = &err;
+    }
+
+    #[test]
+    fn already_borrowed() {
+        let mut rc = Rc::new(0);
+        std::mem::forget(Rc::borrow_mut(&mut rc));
+        assert_eq!(
+            Rc::try_borrow_mut(&mut rc).unwrap_err(),
+            BorrowMutError::AlreadyBorrowed
+        );
+        assert_eq!(Rc::strong_count(&rc), 0);
+        leak(rc);
+    }
+
+    #[test]
+    fn make_mut_keep_weaks() {
+        let mut rc = Rc::new(vec![1]);
+        let weak = Rc::downgrade(&rc);
+        Rc::make_mut_keep_weaks(&mut rc).push(2);
+        assert_eq!(*weak.upgrade().unwrap(), [1, 2]);
+
+        let other = Rc::clone(&rc);
+        let mut mutable = Rc::make_mut_keep_weaks(&mut rc);
+        mutable.push(3);
+        // The original allocation, and its weak references, stay with `other`.
+        assert_eq!(*weak.upgrade().unwrap(), [1, 2]);
+        drop(mutable);
+        assert_eq!(*rc, [1, 2, 3]);
+        assert!(Rc::ptr_eq(&weak.upgrade().unwrap(), &other));
+        assert_eq!(Rc::weak_count(&rc), 0);
+    }
+
+    #[test]
+    fn map() {
+        struct Node {
+            name: &'static str,
+            children: Vec<i32>,
+        }
+
+        let mut rc = Rc::new(Node {
+            name: "root",
+            children: vec![1],
+        });
+        let weak = Rc::downgrade(&rc);
+        let mut children = BorrowRefMut::map(Rc::borrow_mut(&mut rc), |node| &mut node.children);
+        children.push(2);
+        assert!(weak.upgrade().is_none());
+        drop(children);
+        assert_eq!(weak.upgrade().unwrap().children, vec![1, 2]);
+        assert_eq!(rc.name, "root");
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(Rc::weak_count(&rc), 1);
+    }
+
+    #[test]
+    fn filter_map() {
+        let mut rc: Rc<[i32]> = vec![1, 2, 3].into();
+        let weak = Rc::downgrade(&rc);
+        let mutable = Rc::borrow_mut(&mut rc);
+        let mutable = BorrowRefMut::filter_map(mutable, |s| s.get_mut(5)).unwrap_err();
+        let mut last = BorrowRefMut::filter_map(mutable, |s| s.last_mut()).unwrap();
+        *last = 4;
+        assert!(weak.upgrade().is_none());
+        drop(last);
+        assert_eq!(*weak.upgrade().unwrap(), [1, 2, 4]);
+    }
+
+    #[test]
+    fn map_split() {
+        let mut rc: Rc<[i32]> = vec![3, 2, 1, 6, 5, 4].into();
+        let weak = Rc::downgrade(&rc);
+        let (mut left, mut right) =
+            BorrowRefMut::map_split(Rc::borrow_mut(&mut rc), |s| s.split_at_mut(3));
+        left.sort();
+        drop(left);
+        assert!(weak.upgrade().is_none());
+        let (mut a, b) = BorrowRefMut::map_split(right, |s| s.split_at_mut(1));
+        right = b;
+        right.sort();
+        a[0] = 7;
+        drop(right);
+        assert!(weak.upgrade().is_none());
+        drop(a);
+        assert_eq!(*weak.upgrade().unwrap(), [1, 2, 3, 7, 4, 5]);
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(Rc::weak_count(&rc), 1);
+    }
+
+    #[test]
+    fn unwind() {
+        let mut rc: Rc<[i32]> = vec![1, 2].into();
+        let weak = Rc::downgrade(&rc);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let (mut a, _b) =
+                BorrowRefMut::map_split(Rc::borrow_mut(&mut rc), |s| s.split_at_mut(1));
+            a[0] = 3;
+            panic!("oops");
+        }));
+        assert!(result.is_err());
+        // Not poisoned, since this wasn't borrowed via `Poison`.
+        assert_eq!(*weak.upgrade().unwrap(), [3, 2]);
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(Rc::weak_count(&rc), 1);
+    }
+
+    #[test]
+    fn map_unwind() {
+        let mut rc = Rc::new((0, 0));
+        let weak = Rc::downgrade(&rc);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mutable = Rc::borrow_mut(&mut rc);
+            drop(BorrowRefMut::map(mutable, |_| -> &mut i32 {
+                panic!("oops")
+            }));
+        }));
+        assert!(result.is_err());
+        assert!(weak.upgrade().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn allocator() {
+        use crate::OwnedBorrowMut;
+        use core::alloc::{AllocError, Allocator, Layout};
+        use core::ptr::NonNull;
+        use std::alloc::Global;
+
+        #[derive(Default)]
+        struct Counting {
+            allocations: Cell<usize>,
+            deallocations: Cell<usize>,
+        }
+
+        unsafe impl Allocator for &Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.allocations.set(self.allocations.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.deallocations.set(self.deallocations.get() + 1);
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let counting = Counting::default();
+        let mut rc = Rc::new_in(vec![1], &counting);
+        let weak = Rc::downgrade(&rc);
+        let allocations = counting.allocations.get();
+
+        let mut mutable = Rc::borrow_mut(&mut rc);
+        assert!(weak.upgrade().is_none());
+        mutable[0] = 2;
+        drop(mutable);
+
+        let mut owned = Rc::into_borrow_mut(rc).unwrap();
+        owned[0] = 3;
+        let rc = OwnedBorrowMut::into_rc(owned);
+
+        assert_eq!(*weak.upgrade().unwrap(), [3]);
+        assert_eq!(counting.allocations.get(), allocations);
+        assert_eq!(counting.deallocations.get(), 0);
+        drop(rc);
+        drop(weak);
+        assert_eq!(counting.deallocations.get(), 1);
+    }
+
     #[test]
     fn unsize() {
         let mut rc: Rc<[i32]> = vec![0, 2, 1].into();
@@ -202,13 +956,256 @@ mod tests {
         drop(rc);
     }
 
+    #[test]
+    fn with_borrow_mut() {
+        let mut rc = Rc::new(0);
+        let weak = Rc::downgrade(&rc);
+        let result = Rc::with_borrow_mut(&mut rc, |value| {
+            *value += 1;
+            assert!(weak.upgrade().is_none());
+            *value * 2
+        });
+        assert_eq!(result, 2);
+        assert_eq!(*weak.upgrade().unwrap(), 1);
+
+        let _rc2 = Rc::clone(&rc);
+        assert!(Rc::try_with_borrow_mut(&mut rc, |value| *value += 1).is_err());
+        assert_eq!(*rc, 1);
+    }
+
+    #[test]
+    fn with_borrow_mut_unwind() {
+        let mut rc = Rc::new(0);
+        let weak = Rc::downgrade(&rc);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            Rc::with_borrow_mut(&mut rc, |value| {
+                *value += 1;
+                panic!("oops");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(*weak.upgrade().unwrap(), 1);
+    }
+
+    #[test]
+    fn layout_matches() {
+        assert!(hack::rc_layout_matches());
+        assert!(hack::arc_layout_matches());
+        // Cached.
+        assert!(hack::rc_layout_matches());
+    }
+
+    #[test]
+    fn counts() {
+        let mut rc = Rc::new(0);
+        let weak = Rc::downgrade(&rc);
+        drop(Rc::borrow_mut(&mut rc));
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(Rc::weak_count(&rc), 1);
+        drop(weak);
+        assert_eq!(Rc::weak_count(&rc), 0);
+    }
+
+    #[test]
+    fn forget() {
+        let mut rc = Rc::new(Cell::new(1));
+        let weak = Rc::downgrade(&rc);
+        std::mem::forget(Rc::borrow_mut(&mut rc));
+        assert!(weak.upgrade().is_none());
+        drop(weak);
+        // The allocation is leaked, so this is sound.
+        rc.set(2);
+        assert_eq!(rc.get(), 2);
+        // Dropping an `Rc` with a strong count of zero would underflow it.
+        leak(rc);
+    }
+
     /// https://github.com/rust-lang/libs-team/issues/112#issuecomment-1282274231
     #[test]
-    #[ignore = "fails"]
+    #[allow(forgetting_references)]
     fn use_after_free() {
+        let mut rc = Rc::new("asdf".to_string());
+        // Forgetting the `&mut` reference doesn't skip restoring the strong count.
+        Rc::with_borrow_mut(&mut rc, |value| std::mem::forget(value));
+        drop(rc.clone());
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(*rc, "asdf");
+    }
+
+    /// Borrows, mutates, and restores `rc`, with and without a `Weak` reference.
+    fn round_trip<T: ?Sized>(mut rc: Rc<T>, mutate: impl Fn(&mut T)) -> Rc<T> {
+        Rc::with_borrow_mut(&mut rc, &mutate);
+
+        let weak = Rc::downgrade(&rc);
+        let mut mutable = Rc::borrow_mut(&mut rc);
+        mutate(&mut mutable);
+        assert!(weak.upgrade().is_none());
+        drop(mutable);
+        assert!(weak.upgrade().is_some());
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(Rc::weak_count(&rc), 1);
+        rc
+    }
+
+    #[test]
+    fn sized() {
+        let rc = round_trip(Rc::new(0u8), |n| *n += 1);
+        assert_eq!(*rc, 2);
+    }
+
+    #[test]
+    fn slice() {
+        let rc: Rc<[u64]> = vec![1, 2, 3].into();
+        let rc = round_trip(rc, |s| s.rotate_left(1));
+        assert_eq!(*rc, [3, 1, 2]);
+    }
+
+    #[test]
+    fn str() {
+        let rc: Rc<str> = "asdf".into();
+        let rc = round_trip(rc, |s| s.make_ascii_uppercase());
+        assert_eq!(&*rc, "ASDF");
+    }
+
+    #[test]
+    fn dyn_trait() {
+        trait Counter {
+            fn increment(&mut self);
+            fn get(&self) -> u16;
+        }
+
+        impl Counter for u16 {
+            fn increment(&mut self) {
+                *self += 1;
+            }
+
+            fn get(&self) -> u16 {
+                *self
+            }
+        }
+
+        let rc: Rc<dyn Counter> = Rc::new(0u16);
+        let rc = round_trip(rc, |c| c.increment());
+        assert_eq!(rc.get(), 2);
+
+        let rc: Rc<dyn Any> = Rc::new(0u32);
+        let rc = round_trip(rc, |a| *a.downcast_mut::<u32>().unwrap() += 1);
+        assert_eq!(rc.downcast_ref::<u32>(), Some(&2));
+    }
+
+    #[repr(align(4096))]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct AlignedTo4096(u8);
+
+    #[test]
+    fn over_aligned() {
+        let rc = round_trip(Rc::new(AlignedTo4096(0)), |a| {
+            assert_eq!(a as *mut AlignedTo4096 as usize % 4096, 0);
+            a.0 += 1;
+        });
+        assert_eq!(*rc, AlignedTo4096(2));
+
+        let rc: Rc<[AlignedTo4096]> = vec![AlignedTo4096(0); 2].into();
+        let rc = round_trip(rc, |s| {
+            assert_eq!(s.as_ptr() as usize % 4096, 0);
+            s[1].0 += 1;
+        });
+        assert_eq!(*rc, [AlignedTo4096(0), AlignedTo4096(2)]);
+
+        let rc: Rc<dyn Any> = Rc::new(AlignedTo4096(0));
+        let rc = round_trip(rc, |a| a.downcast_mut::<AlignedTo4096>().unwrap().0 += 1);
+        assert_eq!(rc.downcast_ref(), Some(&AlignedTo4096(2)));
+    }
+
+    #[test]
+    fn zero_sized() {
+        struct Zst;
+
+        round_trip(Rc::new(()), |_| {});
+        round_trip(Rc::new(Zst), |_| {});
+        let rc: Rc<[Zst]> = vec![Zst, Zst].into();
+        let rc = round_trip(rc, |s| s.swap(0, 1));
+        assert_eq!(rc.len(), 2);
+    }
+
+    #[test]
+    fn drop_during_borrow() {
+        let inner = Rc::new(0);
+        let mut rc = Rc::new(Some(Rc::clone(&inner)));
+        let weak = Rc::downgrade(&rc);
+        let mut mutable = Rc::borrow_mut(&mut rc);
+        // Drops another `Rc` and the only `Weak` while borrowed.
+        *mutable = None;
+        drop(weak);
+        assert_eq!(Rc::strong_count(&inner), 1);
+        drop(mutable);
+        assert_eq!(Rc::weak_count(&rc), 0);
+        drop(rc);
+    }
+
+    /// The unsound case from the README, which Miri reports as undefined behavior.
+    #[test]
+    #[ignore = "unsound"]
+    fn forget_then_clone() {
         let mut rc = Rc::new("asdf".to_string());
         std::mem::forget(Rc::borrow_mut(&mut rc));
         drop(rc.clone());
-        println!("use after free: {rc:?}");
+        assert_eq!(*rc, "asdf");
+    }
+
+    #[test]
+    fn data_offset_align() {
+        let header = 2 * size_of::<usize>();
+        for align in [1, 2, 4, 8] {
+            assert_eq!(hack::data_offset_align(align), header);
+        }
+        assert_eq!(hack::data_offset_align(64), 64);
+        assert_eq!(hack::data_offset_align(4096), 4096);
+    }
+
+    #[test]
+    fn downcast() {
+        let mut rc: Rc<dyn Any> = Rc::new(0u8);
+        let weak = Rc::downgrade(&rc);
+        let mutable = Rc::borrow_mut(&mut rc);
+        let mutable = mutable.downcast::<u16>().unwrap_err();
+        let mut mutable = mutable.downcast::<u8>().unwrap();
+        *mutable += 1;
+        assert!(weak.upgrade().is_none());
+        drop(mutable);
+        assert_eq!(weak.upgrade().unwrap().downcast_ref(), Some(&1u8));
+
+        let mut rc: Rc<dyn Any + Send + Sync> = Rc::new(AlignedTo4096(0));
+        let mutable = Rc::borrow_mut(&mut rc);
+        mutable.downcast::<AlignedTo4096>().unwrap().0 += 1;
+        assert_eq!(rc.downcast_ref(), Some(&AlignedTo4096(1)));
+
+        let mut rc: Rc<dyn Any + Send> = Rc::new(0u8);
+        assert!(Rc::borrow_mut(&mut rc).downcast::<()>().is_err());
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn replace() {
+        #[derive(Debug, Default, PartialEq)]
+        enum State {
+            #[default]
+            Idle,
+            Running(u8),
+        }
+
+        let mut rc = Rc::new(State::Running(1));
+        let weak = Rc::downgrade(&rc);
+        let mut mutable = Rc::borrow_mut(&mut rc);
+        assert_eq!(BorrowRefMut::take(&mut mutable), State::Running(1));
+        assert_eq!(*mutable, State::Idle);
+        assert_eq!(
+            BorrowRefMut::replace(&mut mutable, State::Running(2)),
+            State::Idle
+        );
+        drop(mutable);
+        assert!(Rc::ptr_eq(&weak.upgrade().unwrap(), &rc));
+        assert_eq!(*rc, State::Running(2));
     }
 }
@@ -0,0 +1,151 @@
+use crate::{BorrowMutError, BorrowRefMut, RcBorrowMut};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The reason mutably borrowing multiple `Rc`s at once failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ManyBorrowError {
+    /// The `Rc`s at these indices point to the same contents.
+    Duplicate { first: usize, second: usize },
+    /// The `Rc` at this index couldn't be borrowed.
+    Borrow { index: usize, error: BorrowMutError },
+}
+
+impl fmt::Display for ManyBorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duplicate { first, second } => write!(
+                f,
+                "cannot borrow mutably, Rcs at indices {first} and {second} are the same"
+            ),
+            Self::Borrow { index, error } => write!(f, "Rc at index {index}: {error}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ManyBorrowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Duplicate { .. } => None,
+            Self::Borrow { error, .. } => Some(error),
+        }
+    }
+}
+
+/// Mutably borrows the contents of several Rcs at once.
+///
+/// Succeeds if each argument is the only strong reference to its contents. Otherwise, none are
+/// borrowed.
+#[track_caller]
+pub fn borrow_mut_many<'a, T: ?Sized, const N: usize>(
+    rcs: [&'a mut Rc<T>; N],
+) -> Result<[BorrowRefMut<'a, T>; N], ManyBorrowError> {
+    match borrow_mut_iter(rcs)?.try_into() {
+        Ok(borrows) => Ok(borrows),
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Mutably borrows the contents of several Rcs at once, e.g. from `slice.iter_mut()`.
+///
+/// Succeeds if each argument is the only strong reference to its contents. Otherwise, none are
+/// borrowed.
+#[track_caller]
+pub fn borrow_mut_iter<'a, T: ?Sized + 'a>(
+    rcs: impl IntoIterator<Item = &'a mut Rc<T>>,
+) -> Result<Vec<BorrowRefMut<'a, T>>, ManyBorrowError> {
+    let rcs = rcs.into_iter().collect::<Vec<_>>();
+
+    // Distinct `Rc`s to the same contents would fail to borrow anyway, but this is clearer.
+    let mut addresses = rcs
+        .iter()
+        .enumerate()
+        .map(|(index, rc)| (Rc::as_ptr(rc) as *const (), index))
+        .collect::<Vec<_>>();
+    addresses.sort_unstable();
+    if let Some(pair) = addresses.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(ManyBorrowError::Duplicate {
+            first: pair[0].1,
+            second: pair[1].1,
+        });
+    }
+
+    // If one fails, dropping the others restores their strong counts. This is a loop, since
+    // closures would lose the caller's location.
+    let mut borrows = Vec::with_capacity(rcs.len());
+    for (index, rc) in rcs.into_iter().enumerate() {
+        match Rc::try_borrow_mut(rc) {
+            Ok(mutable) => borrows.push(mutable),
+            Err(error) => return Err(ManyBorrowError::Borrow { index, error }),
+        }
+    }
+    Ok(borrows)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{borrow_mut_iter, borrow_mut_many, BorrowMutError, ManyBorrowError};
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    #[test]
+    fn many() {
+        let mut a = Rc::new(1);
+        let mut b = Rc::new(2);
+        let weak = Rc::downgrade(&b);
+        let [mut a_mut, mut b_mut] = borrow_mut_many([&mut a, &mut b]).unwrap();
+        core::mem::swap(&mut *a_mut, &mut *b_mut);
+        assert!(weak.upgrade().is_none());
+        drop((a_mut, b_mut));
+        assert_eq!((*a, *b), (2, 1));
+        assert_eq!(*weak.upgrade().unwrap(), 1);
+    }
+
+    #[test]
+    fn rollback() {
+        let mut rcs = (0..4).map(Rc::new).collect::<Vec<_>>();
+        let weaks = rcs.iter().map(Rc::downgrade).collect::<Vec<_>>();
+        let _other = Rc::clone(&rcs[2]);
+        assert_eq!(
+            borrow_mut_iter(rcs.iter_mut()).unwrap_err(),
+            ManyBorrowError::Borrow {
+                index: 2,
+                error: BorrowMutError::OtherStrongReferencesExist {
+                    strong_count: 2,
+                    weak_count: 1
+                }
+            }
+        );
+        assert!(weaks.iter().all(|weak| weak.upgrade().is_some()));
+        assert!(rcs
+            .iter()
+            .all(|rc| Rc::strong_count(rc) == 1 + (**rc == 2) as usize));
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut a = Rc::new(0);
+        let mut b = Rc::new(1);
+        let mut c = Rc::clone(&a);
+        assert_eq!(
+            borrow_mut_many([&mut a, &mut b, &mut c]).unwrap_err(),
+            ManyBorrowError::Duplicate {
+                first: 0,
+                second: 2
+            }
+        );
+        assert_eq!(Rc::strong_count(&a), 2);
+        assert_eq!(Rc::strong_count(&b), 1);
+    }
+
+    #[test]
+    fn iter() {
+        let mut rcs: Vec<Rc<[i32]>> = (0..3).map(|n| [n, -n].into()).collect();
+        for mut mutable in borrow_mut_iter(&mut rcs).unwrap() {
+            mutable.sort();
+        }
+        assert_eq!(*rcs[2], [-2, 2]);
+    }
+}
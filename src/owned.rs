@@ -0,0 +1,193 @@
+use crate::Restore;
+use alloc::rc::Rc;
+use core::fmt;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll};
+
+/// A mutable handle to the contents of an `Rc`, which owns the `Rc`.
+///
+/// Unlike [`BorrowRefMut`](crate::BorrowRefMut), it has no lifetime, so it can be stored or
+/// moved anywhere. `Weak` references can't be upgraded until it is dropped or converted back
+/// with [`OwnedBorrowMut::into_rc`].
+///
+/// `R` is the type of the `Rc`, which only differs from the default with a custom allocator.
+///
+/// The handle is invariant in `T`, since `Weak` references to the `Rc` would otherwise see
+/// values that don't live long enough for their type:
+///
+/// ```compile_fail,E0597
+/// use rc_borrow_mut::{OwnedBorrowMut, RcBorrowMut};
+/// use std::rc::Rc;
+///
+/// let owned: OwnedBorrowMut<&'static str> = Rc::into_borrow_mut(Rc::new("static")).unwrap();
+/// let short_lived = String::from("short-lived");
+/// let mut owned: OwnedBorrowMut<&str> = owned;
+/// *owned = &short_lived;
+/// ```
+pub struct OwnedBorrowMut<T: ?Sized, R = Rc<T>> {
+    value: NonNull<T>,
+    // Dropped before `rc`, so the value is dropped normally if it was the last reference.
+    restore: Restore,
+    rc: R,
+    _invariant: PhantomData<fn(T) -> T>,
+}
+
+impl<T: ?Sized, R> OwnedBorrowMut<T, R> {
+    /// `rc` must own `value`, which `restore` must have borrowed.
+    pub(crate) fn new(value: NonNull<T>, restore: Restore, rc: R) -> Self {
+        Self {
+            value,
+            restore,
+            rc,
+            _invariant: PhantomData,
+        }
+    }
+
+    /// Restores the strong count, giving back the `Rc`.
+    ///
+    /// This is an associated function that needs to be used as `OwnedBorrowMut::into_rc(...)`,
+    /// so as not to interfere with methods of the same name on the contents.
+    pub fn into_rc(me: Self) -> R {
+        let Self { restore, rc, .. } = me;
+        drop(restore);
+        rc
+    }
+
+    /// Restores the strong count of a handle from [`PinRcBorrowMut::into_borrow_mut`], giving
+    /// back the pinned `Rc`.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `OwnedBorrowMut::into_pin_rc(...)`, so as not to interfere with methods of the same name
+    /// on the contents.
+    ///
+    /// [`PinRcBorrowMut::into_borrow_mut`]: crate::PinRcBorrowMut::into_borrow_mut
+    pub fn into_pin_rc(me: Pin<Self>) -> Pin<R>
+    where
+        R: Deref<Target = T>,
+    {
+        // The contents aren't moved, and stay pinned in the `Rc`.
+        unsafe { Pin::new_unchecked(Self::into_rc(Pin::into_inner_unchecked(me))) }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, R> fmt::Debug for OwnedBorrowMut<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display, R> fmt::Display for OwnedBorrowMut<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized, R> Deref for OwnedBorrowMut<T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized, R> DerefMut for OwnedBorrowMut<T, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.value.as_mut() }
+    }
+}
+
+/// Polls the contents while they're borrowed, so `Weak` references held by wakers can't be
+/// upgraded during the poll.
+impl<F: ?Sized + Future + Unpin, R: Unpin> Future for OwnedBorrowMut<F, R> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        Pin::new(&mut **self).poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{OwnedBorrowMut, RcBorrowMut};
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll, Waker};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::vec;
+
+    #[test]
+    fn into_rc() {
+        let rc = Rc::new(vec![1]);
+        let weak = Rc::downgrade(&rc);
+        let mut owned = Rc::into_borrow_mut(rc).unwrap();
+        owned.push(2);
+        assert!(weak.upgrade().is_none());
+
+        // No lifetime, so it can be kept around.
+        let mut checked_out = vec![owned];
+        checked_out[0].push(3);
+        assert!(weak.upgrade().is_none());
+
+        let rc = OwnedBorrowMut::into_rc(checked_out.pop().unwrap());
+        assert_eq!(*weak.upgrade().unwrap(), [1, 2, 3]);
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(Rc::weak_count(&rc), 1);
+    }
+
+    #[test]
+    fn shared() {
+        let rc = Rc::new(0);
+        let rc2 = Rc::clone(&rc);
+        let rc = Rc::into_borrow_mut(rc).unwrap_err();
+        assert!(Rc::ptr_eq(&rc, &rc2));
+    }
+
+    #[test]
+    fn drop_value() {
+        #[derive(Debug)]
+        struct Dropper<'a>(&'a Cell<bool>);
+
+        impl Drop for Dropper<'_> {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let dropped = Cell::new(false);
+        let rc = Rc::new(Dropper(&dropped));
+        let weak = Rc::downgrade(&rc);
+        let owned = Rc::into_borrow_mut(rc).unwrap();
+        assert!(!dropped.get());
+        drop(owned);
+        assert!(dropped.get());
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+    }
+
+    #[test]
+    fn future() {
+        let mut polled = false;
+        let rc = Rc::new(core::future::poll_fn(move |cx| {
+            if polled {
+                Poll::Ready(1)
+            } else {
+                polled = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }));
+        let weak = Rc::downgrade(&rc);
+        let mut owned = Rc::into_borrow_mut(rc).unwrap();
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(Pin::new(&mut owned).poll(&mut cx), Poll::Pending);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(Pin::new(&mut owned).poll(&mut cx), Poll::Ready(1));
+        let _rc = OwnedBorrowMut::into_rc(owned);
+        assert!(weak.upgrade().is_some());
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{unwrap_borrow, BorrowMutError, BorrowRefMut, OwnedBorrowMut, RcBorrowMut};
+use alloc::rc::Rc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Mutably borrows the pinned contents of a `Pin<Rc<T>>`, like [`RcBorrowMut`].
+///
+/// The guard is pinned, so it only exposes `Pin<&mut T>`, or `&mut T` if `T: Unpin`.
+pub trait PinRcBorrowMut<T: ?Sized> {
+    /// Mutably borrows the pinned contents of the Rc.
+    ///
+    /// # Panics
+    ///
+    /// If there are other strong references.
+    #[track_caller]
+    fn borrow_mut(me: &mut Self) -> Pin<BorrowRefMut<'_, T>> {
+        Self::try_borrow_mut(me).unwrap()
+    }
+
+    /// Mutably borrows the pinned contents of the Rc.
+    ///
+    /// Succeeds if the argument is the only strong reference, and the layout of `Rc`
+    /// (checked once) is as expected.
+    #[track_caller]
+    fn try_borrow_mut(me: &mut Self) -> Result<Pin<BorrowRefMut<'_, T>>, BorrowMutError>;
+
+    /// Mutably borrows the pinned contents of the Rc for the duration of `f`.
+    ///
+    /// # Panics
+    ///
+    /// If there are other strong references.
+    #[track_caller]
+    fn with_borrow_mut<R>(me: &mut Self, f: impl FnOnce(Pin<&mut T>) -> R) -> R {
+        let mut mutable = Self::borrow_mut(me);
+        f(mutable.as_mut())
+    }
+
+    /// Mutably borrows the pinned contents of the Rc for the duration of `f`.
+    ///
+    /// Succeeds if the argument is the only strong reference.
+    #[track_caller]
+    fn try_with_borrow_mut<R>(
+        me: &mut Self,
+        f: impl FnOnce(Pin<&mut T>) -> R,
+    ) -> Result<R, BorrowMutError> {
+        let mut mutable = Self::try_borrow_mut(me)?;
+        Ok(f(mutable.as_mut()))
+    }
+
+    /// Mutably borrows the pinned contents of the Rc, taking ownership of it. A pinned future
+    /// can be polled or awaited through the returned handle, since `Pin` forwards `Future`.
+    ///
+    /// Succeeds if the argument is the only strong reference, otherwise it is returned.
+    #[track_caller]
+    fn into_borrow_mut(me: Self) -> Result<Pin<OwnedBorrowMut<T>>, Self>
+    where
+        Self: Sized;
+}
+
+impl<T: ?Sized> PinRcBorrowMut<T> for Pin<Rc<T>> {
+    #[track_caller]
+    fn borrow_mut(me: &mut Self) -> Pin<BorrowRefMut<'_, T>> {
+        // Doesn't dereference the `Pin`, since the contents may be borrowed.
+        let value = Rc::as_ptr(unsafe { &*(me as *const Self as *const Rc<T>) });
+        unwrap_borrow(Self::try_borrow_mut(me), value.cast())
+    }
+
+    #[track_caller]
+    fn try_borrow_mut(me: &mut Self) -> Result<Pin<BorrowRefMut<'_, T>>, BorrowMutError> {
+        unsafe {
+            // `Pin` is `repr(transparent)`, and the `Rc` itself is never moved out.
+            let rc = &mut *(me as *mut Self as *mut Rc<T>);
+            // `BorrowRefMut` doesn't move its contents, and can't be moved out of the `Pin`
+            // unless `T: Unpin`.
+            Rc::try_borrow_mut(rc).map(|mutable| Pin::new_unchecked(mutable))
+        }
+    }
+
+    #[track_caller]
+    fn into_borrow_mut(me: Self) -> Result<Pin<OwnedBorrowMut<T>>, Self> {
+        unsafe {
+            // As above, the `Rc` is only moved between `Pin`s, and the guard doesn't move its
+            // contents.
+            match Rc::into_borrow_mut(Pin::into_inner_unchecked(me)) {
+                Ok(owned) => Ok(Pin::new_unchecked(owned)),
+                Err(rc) => Err(Pin::new_unchecked(rc)),
+            }
+        }
+    }
+}
+
+/// Polls a future stored in an `Rc`, while mutably borrowing it. `Weak` references held by
+/// wakers can't be upgraded during the poll, so a task can't be polled reentrantly.
+///
+/// # Panics
+///
+/// If there are other strong references.
+#[track_caller]
+pub fn poll_rc<F: ?Sized + Future>(rc: &mut Pin<Rc<F>>, cx: &mut Context<'_>) -> Poll<F::Output> {
+    Pin::with_borrow_mut(rc, |future| future.poll(cx))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        poll_rc, BorrowMutError, OwnedBorrowMut, PinRcBorrowMut, UpgradeState, WeakBorrowExt,
+    };
+    use core::cell::{Cell, RefCell};
+    use core::future::{poll_fn, Future};
+    use core::marker::PhantomPinned;
+    use core::mem::ManuallyDrop;
+    use core::pin::Pin;
+    use core::ptr;
+    use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+    use std::boxed::Box;
+    use std::collections::VecDeque;
+    use std::rc::{Rc, Weak};
+    use std::vec::Vec;
+    use std::{thread_local, vec};
+
+    #[test]
+    fn unpin() {
+        let mut rc = Rc::pin(0);
+        let mut mutable = Pin::borrow_mut(&mut rc);
+        *mutable += 1;
+        drop(mutable);
+        assert_eq!(*rc, 1);
+    }
+
+    #[test]
+    fn not_unpin() {
+        struct Node {
+            value: u8,
+            _pinned: PhantomPinned,
+        }
+
+        let rc = Rc::new(Node {
+            value: 0,
+            _pinned: PhantomPinned,
+        });
+        let weak = Rc::downgrade(&rc);
+        let mut rc = unsafe { Pin::new_unchecked(rc) };
+        let mut mutable = Pin::borrow_mut(&mut rc);
+        unsafe { mutable.as_mut().get_unchecked_mut().value += 1 };
+        assert!(weak.upgrade().is_none());
+        drop(mutable);
+        assert_eq!(weak.upgrade().unwrap().value, 1);
+
+        let _clone = Pin::clone(&rc);
+        assert!(matches!(
+            Pin::try_borrow_mut(&mut rc),
+            Err(BorrowMutError::OtherStrongReferencesExist { .. })
+        ));
+    }
+
+    #[test]
+    fn poll() {
+        let mut count = 0;
+        let mut rc = Rc::pin(core::future::poll_fn(move |_| {
+            count += 1;
+            if count < 2 {
+                Poll::Pending
+            } else {
+                Poll::Ready(count)
+            }
+        }));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(
+            Pin::with_borrow_mut(&mut rc, |f| f.poll(&mut cx)),
+            Poll::Pending
+        );
+        let mut mutable = Pin::borrow_mut(&mut rc);
+        assert_eq!(mutable.as_mut().poll(&mut cx), Poll::Ready(2));
+        drop(mutable);
+
+        let mut rc: Pin<Rc<dyn Future<Output = u8>>> = Rc::pin(async { 1 });
+        let mut mutable = Pin::borrow_mut(&mut rc);
+        assert_eq!(mutable.as_mut().poll(&mut cx), Poll::Ready(1));
+        // The `Rc` is restored when the guard is dropped.
+        drop(mutable);
+        assert_eq!(
+            Rc::strong_count(&unsafe { Pin::into_inner_unchecked(rc) }),
+            1
+        );
+    }
+
+    #[test]
+    fn owned() {
+        let mut yielded = false;
+        let rc: Rc<dyn Future<Output = u8>> = Rc::new(async move {
+            poll_fn(|cx| {
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+            1
+        });
+        let weak = Rc::downgrade(&rc);
+        let rc = unsafe { Pin::new_unchecked(rc) };
+        let other = Pin::clone(&rc);
+        let Err(rc) = Pin::into_borrow_mut(rc) else {
+            panic!("borrowed despite another strong reference");
+        };
+        drop(other);
+
+        // `Pin<OwnedBorrowMut<dyn Future>>` is a future, though `dyn Future` isn't `Unpin`.
+        let Ok(mut owned) = Pin::into_borrow_mut(rc) else {
+            panic!("failed to borrow the only strong reference");
+        };
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(owned.as_mut().poll(&mut cx), Poll::Pending);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(owned.as_mut().poll(&mut cx), Poll::Ready(1));
+
+        let rc = OwnedBorrowMut::into_pin_rc(owned);
+        assert!(weak.upgrade().is_some());
+        drop(rc);
+        assert!(weak.upgrade().is_none());
+    }
+
+    type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+    thread_local! {
+        /// Tasks that were woken, and whether they were borrowed (being polled) at the time.
+        static WOKEN: RefCell<Vec<(Weak<Task>, bool)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Makes a waker holding a `Weak` reference to `task`. It must not leave this thread.
+    fn waker(task: Weak<Task>) -> Waker {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+
+        unsafe fn clone(data: *const ()) -> RawWaker {
+            let task = ManuallyDrop::new(Weak::from_raw(data as *const Task));
+            RawWaker::new(Weak::into_raw(Weak::clone(&task)) as *const (), &VTABLE)
+        }
+
+        unsafe fn wake(data: *const ()) {
+            wake_by_ref(data);
+            drop(data);
+        }
+
+        unsafe fn wake_by_ref(data: *const ()) {
+            let task = ManuallyDrop::new(Weak::from_raw(data as *const Task));
+            let borrowed = matches!(task.upgrade_state(), UpgradeState::Borrowed);
+            WOKEN.with_borrow_mut(|woken| woken.push((Weak::clone(&task), borrowed)));
+        }
+
+        unsafe fn drop(data: *const ()) {
+            core::mem::drop(Weak::from_raw(data as *const Task));
+        }
+
+        let data = Weak::into_raw(task) as *const ();
+        unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) }
+    }
+
+    /// Runs the tasks to completion, returning whether each wake happened during a poll.
+    fn run(futures: Vec<Task>) -> Vec<bool> {
+        let mut tasks = Vec::new();
+        let mut ready = VecDeque::new();
+        for future in futures {
+            let task = Rc::new(future);
+            ready.push_back(Rc::downgrade(&task));
+            tasks.push(Pin::new(task));
+        }
+
+        let mut wakes = Vec::new();
+        while let Some(weak) = ready.pop_front() {
+            // Skips tasks that completed after being woken.
+            let Some(index) = tasks
+                .iter()
+                .position(|task| ptr::eq(&**task, Weak::as_ptr(&weak)))
+            else {
+                continue;
+            };
+            let waker = waker(weak);
+            if poll_rc(&mut tasks[index], &mut Context::from_waker(&waker)).is_ready() {
+                tasks.swap_remove(index);
+            }
+            for (weak, borrowed) in WOKEN.take() {
+                ready.push_back(weak);
+                wakes.push(borrowed);
+            }
+        }
+        assert!(tasks.is_empty());
+        wakes
+    }
+
+    #[test]
+    fn executor() {
+        let registered = Rc::new(Cell::new(None::<Waker>));
+        let done = Rc::new(Cell::new(false));
+        let (registered2, done2) = (Rc::clone(&registered), Rc::clone(&done));
+        let mut yielded = false;
+
+        let wakes = run(vec![
+            // Waits to be woken by the next task.
+            Box::pin(poll_fn(move |cx| {
+                if done.get() {
+                    Poll::Ready(())
+                } else {
+                    registered.set(Some(cx.waker().clone()));
+                    Poll::Pending
+                }
+            })),
+            Box::pin(poll_fn(move |_| {
+                done2.set(true);
+                registered2.take().unwrap().wake();
+                Poll::Ready(())
+            })),
+            // Wakes itself, while borrowed.
+            Box::pin(poll_fn(move |cx| {
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })),
+        ]);
+        assert_eq!(wakes, [false, true]);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    unwrap_borrow, BorrowMutError, BorrowRefMut, RcBorrowMut, UpgradeState, WeakBorrowExt,
+};
+use alloc::rc::{Rc, Weak};
+use core::cell::Cell;
+use core::fmt;
+use core::ptr;
+use std::sync::PoisonError;
+
+/// Contents of an `Rc` that are poisoned if a panic occurs while they are mutably borrowed with
+/// [`Poison::try_borrow_mut`], like a `Mutex`.
+///
+/// Without poisoning, the contents of an `Rc` that were left half-mutated by a panic become
+/// visible again via `Weak::upgrade` as soon as the borrow ends.
+pub struct Poison<T: ?Sized> {
+    poisoned: Cell<bool>,
+    value: T,
+}
+
+impl<T> Poison<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            poisoned: Cell::new(false),
+            value,
+        }
+    }
+
+    /// Returns the contents, or an error containing them if poisoned.
+    pub fn into_inner(self) -> Result<T, PoisonError<T>> {
+        if self.is_poisoned() {
+            Err(PoisonError::new(self.value))
+        } else {
+            Ok(self.value)
+        }
+    }
+}
+
+impl<T: ?Sized> Poison<T> {
+    /// Whether a borrow ended during a panic.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.get()
+    }
+
+    /// Marks the contents as valid again.
+    pub fn clear_poison(&self) {
+        self.poisoned.set(false);
+    }
+
+    /// Returns the contents, or an error containing them if poisoned.
+    pub fn get(&self) -> Result<&T, PoisonError<&T>> {
+        if self.is_poisoned() {
+            Err(PoisonError::new(&self.value))
+        } else {
+            Ok(&self.value)
+        }
+    }
+
+    /// Returns the contents, or an error containing them if poisoned.
+    pub fn get_mut(&mut self) -> Result<&mut T, PoisonError<&mut T>> {
+        if self.is_poisoned() {
+            Err(PoisonError::new(&mut self.value))
+        } else {
+            Ok(&mut self.value)
+        }
+    }
+
+    /// Mutably borrows the contents of the Rc, poisoning them if the borrow ends during a panic.
+    ///
+    /// # Panics
+    ///
+    /// If there are other strong references, or the contents are poisoned.
+    #[track_caller]
+    pub fn borrow_mut(me: &mut Rc<Self>) -> BorrowRefMut<'_, T> {
+        let value = Rc::as_ptr(me).cast();
+        unwrap_borrow(Self::try_borrow_mut(me), value)
+    }
+
+    /// Mutably borrows the contents of the Rc, poisoning them if the borrow ends during a panic.
+    ///
+    /// Succeeds if the argument is the only strong reference, and the contents aren't poisoned.
+    #[track_caller]
+    pub fn try_borrow_mut(me: &mut Rc<Self>) -> Result<BorrowRefMut<'_, T>, BorrowMutError> {
+        let mut mutable = Rc::try_borrow_mut(me)?;
+        if mutable.is_poisoned() {
+            return Err(BorrowMutError::Poisoned);
+        }
+        unsafe {
+            // `BorrowRefMut::map` only exposes `value`.
+            mutable.poison_on_panic(|poison| ptr::addr_of_mut!((*poison).poisoned));
+        }
+        Ok(BorrowRefMut::map(mutable, |poison| &mut poison.value))
+    }
+
+    /// Attempts to upgrade the Weak, like [`WeakBorrowExt::upgrade_state`], but fails if the
+    /// contents are poisoned.
+    pub fn upgrade(weak: &Weak<Self>) -> Result<UpgradeState<Self>, PoisonError<Rc<Self>>>
+    where
+        Weak<Self>: WeakBorrowExt<Self>,
+    {
+        match weak.upgrade_state() {
+            UpgradeState::Alive(rc) if rc.is_poisoned() => Err(PoisonError::new(rc)),
+            state => Ok(state),
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Poison<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Poison")
+            .field("poisoned", &self.is_poisoned())
+            .field("value", &&self.value)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BorrowMutError, Poison, UpgradeState};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::rc::Rc;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    fn not_poisoned() {
+        let mut rc = Rc::new(Poison::new(vec![1]));
+        let weak = Rc::downgrade(&rc);
+        Poison::borrow_mut(&mut rc).push(2);
+        assert!(!rc.is_poisoned());
+        let rc2 = Poison::upgrade(&weak).unwrap();
+        assert!(matches!(rc2, UpgradeState::Alive(_)));
+    }
+
+    #[test]
+    fn poisoned() {
+        let mut rc = Rc::new(Poison::new(vec![1, 2]));
+        let weak = Rc::downgrade(&rc);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mut mutable = Poison::borrow_mut(&mut rc);
+            mutable.push(3);
+            assert!(matches!(Poison::upgrade(&weak), Ok(UpgradeState::Borrowed)));
+            panic!("oops");
+        }));
+        assert!(result.is_err());
+        assert!(rc.is_poisoned());
+        assert_eq!(
+            Poison::try_borrow_mut(&mut rc).unwrap_err(),
+            BorrowMutError::Poisoned
+        );
+        let upgraded = Poison::upgrade(&weak).unwrap_err().into_inner();
+        assert_eq!(*upgraded.get().unwrap_err().into_inner(), [1, 2, 3]);
+        drop(upgraded);
+
+        rc.clear_poison();
+        Poison::borrow_mut(&mut rc).pop();
+        assert_eq!(*rc.get().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn map_poisoned() {
+        let mut rc = Rc::new(Poison::new((Vec::<i32>::new(), 0)));
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mutable = Poison::borrow_mut(&mut rc);
+            let _field = crate::BorrowRefMut::map(mutable, |(_, n)| n);
+            panic!("oops");
+        }));
+        assert!(result.is_err());
+        assert!(rc.is_poisoned());
+    }
+}
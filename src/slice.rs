@@ -0,0 +1,149 @@
+use crate::BorrowRefMut;
+use core::fmt;
+use core::iter::FusedIterator;
+
+impl<'a, T> BorrowRefMut<'a, [T]> {
+    /// Splits the borrowed slice into two at an index, like `<[T]>::split_at_mut`.
+    ///
+    /// The strong count of the original `Rc` is restored when both returned handles have been
+    /// dropped.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `BorrowRefMut::split_at_mut(...)`, so as not to interfere with the method on slices.
+    ///
+    /// # Panics
+    ///
+    /// If `mid > len`.
+    #[track_caller]
+    pub fn split_at_mut(orig: Self, mid: usize) -> (Self, Self) {
+        BorrowRefMut::map_split(orig, |slice| slice.split_at_mut(mid))
+    }
+
+    /// Returns an iterator over `chunk_size` elements of the borrowed slice at a time, like
+    /// `<[T]>::chunks_mut`, which yields handles that may outlive it.
+    ///
+    /// The strong count of the original `Rc` is restored when the iterator and every handle it
+    /// yielded have been dropped.
+    ///
+    /// This is an associated function that needs to be used as `BorrowRefMut::chunks_mut(...)`,
+    /// so as not to interfere with the method on slices.
+    ///
+    /// # Panics
+    ///
+    /// If `chunk_size` is zero.
+    #[track_caller]
+    pub fn chunks_mut(orig: Self, chunk_size: usize) -> ChunksMut<'a, T> {
+        assert!(chunk_size != 0, "chunk size must be non-zero");
+        ChunksMut {
+            rest: Some(orig),
+            chunk_size,
+        }
+    }
+}
+
+/// An iterator over chunks of a mutably borrowed slice, returned by
+/// [`BorrowRefMut::chunks_mut`].
+pub struct ChunksMut<'a, T> {
+    /// `None` once the slice is exhausted.
+    rest: Option<BorrowRefMut<'a, [T]>>,
+    chunk_size: usize,
+}
+
+impl<'a, T> Iterator for ChunksMut<'a, T> {
+    type Item = BorrowRefMut<'a, [T]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.take().filter(|rest| !rest.is_empty())?;
+        if rest.len() <= self.chunk_size {
+            return Some(rest);
+        }
+        let (chunk, rest) = BorrowRefMut::split_at_mut(rest, self.chunk_size);
+        self.rest = Some(rest);
+        Some(chunk)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self
+            .rest
+            .as_ref()
+            .map_or(0, |rest| rest.len().div_ceil(self.chunk_size));
+        (len, Some(len))
+    }
+}
+
+impl<T> ExactSizeIterator for ChunksMut<'_, T> {}
+
+impl<T> FusedIterator for ChunksMut<'_, T> {}
+
+impl<T: fmt::Debug> fmt::Debug for ChunksMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunksMut")
+            .field("rest", &self.rest)
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BorrowRefMut, RcBorrowMut};
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    #[test]
+    fn split_at_mut() {
+        let mut rc: Rc<[u8]> = Rc::from([1, 2, 3]);
+        let weak = Rc::downgrade(&rc);
+        let (mut a, mut b) = BorrowRefMut::split_at_mut(Rc::borrow_mut(&mut rc), 1);
+        a[0] = 4;
+        b.copy_from_slice(&[5, 6]);
+        drop(a);
+        assert!(weak.upgrade().is_none());
+        drop(b);
+        assert_eq!(*weak.upgrade().unwrap(), [4, 5, 6]);
+    }
+
+    #[test]
+    fn chunks_mut() {
+        let mut rc: Rc<[u8]> = Rc::from([1, 2, 3, 4, 5]);
+        let weak = Rc::downgrade(&rc);
+        let chunks = BorrowRefMut::chunks_mut(Rc::borrow_mut(&mut rc), 2);
+        assert_eq!(chunks.len(), 3);
+        let mut chunks = chunks.collect::<Vec<_>>();
+        assert_eq!(
+            chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+            [2, 2, 1]
+        );
+        for chunk in &mut chunks {
+            chunk.reverse();
+        }
+        assert!(weak.upgrade().is_none());
+        drop(chunks);
+        assert_eq!(*rc, [2, 1, 4, 3, 5]);
+
+        let mut chunks = BorrowRefMut::chunks_mut(Rc::borrow_mut(&mut rc), 10);
+        assert_eq!(chunks.next().unwrap().len(), 5);
+        assert!(chunks.next().is_none());
+        drop(chunks);
+        assert_eq!(Rc::strong_count(&rc), 1);
+
+        let mut empty: Rc<[u8]> = Rc::from([]);
+        assert_eq!(
+            BorrowRefMut::chunks_mut(Rc::borrow_mut(&mut empty), 1).len(),
+            0
+        );
+    }
+
+    #[test]
+    fn str() {
+        let mut rc: Rc<str> = Rc::from("interned");
+        let weak = Rc::downgrade(&rc);
+        let mut mutable = Rc::borrow_mut(&mut rc);
+        mutable.make_ascii_uppercase();
+        let (a, b) = mutable.split_at_mut(4);
+        b.make_ascii_lowercase();
+        assert_eq!(a, "INTE");
+        drop(mutable);
+        assert_eq!(&*weak.upgrade().unwrap(), "INTErned");
+    }
+}
@@ -0,0 +1,131 @@
+use crate::hack;
+use alloc::rc::{Rc, Weak};
+
+/// The result of [`WeakBorrowExt::upgrade_state`].
+#[derive(Debug)]
+pub enum UpgradeState<T: ?Sized> {
+    /// The value is alive, and was upgraded.
+    Alive(Rc<T>),
+    /// The value is alive, but mutably borrowed, so it can't be upgraded until the borrow ends.
+    Borrowed,
+    /// The value was dropped (or the `Weak` was created with `Weak::new`).
+    Dead,
+}
+
+impl<T: ?Sized> UpgradeState<T> {
+    /// Returns the upgraded `Rc`, if the value was alive and not borrowed.
+    pub fn alive(self) -> Option<Rc<T>> {
+        match self {
+            Self::Alive(rc) => Some(rc),
+            _ => None,
+        }
+    }
+}
+
+pub trait WeakBorrowExt<T: ?Sized> {
+    /// Attempts to upgrade the Weak, like `Weak::upgrade`, but distinguishes a mutably
+    /// borrowed value from a dropped one.
+    fn upgrade_state(&self) -> UpgradeState<T>;
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized> WeakBorrowExt<T> for Weak<T> {
+    fn upgrade_state(&self) -> UpgradeState<T> {
+        // Only reads the metadata, so the value may be borrowed or dropped.
+        let align = unsafe { core::mem::align_of_val_raw(Weak::as_ptr(self)) };
+        upgrade_state(self, align)
+    }
+}
+
+// Without `align_of_val_raw`, we can't find the alignment of a borrowed or dropped value in
+// general, but we can for these types.
+#[cfg(not(feature = "nightly"))]
+impl<T> WeakBorrowExt<T> for Weak<T> {
+    fn upgrade_state(&self) -> UpgradeState<T> {
+        upgrade_state(self, core::mem::align_of::<T>())
+    }
+}
+
+#[cfg(not(feature = "nightly"))]
+impl<T> WeakBorrowExt<[T]> for Weak<[T]> {
+    fn upgrade_state(&self) -> UpgradeState<[T]> {
+        upgrade_state(self, core::mem::align_of::<T>())
+    }
+}
+
+#[cfg(not(feature = "nightly"))]
+impl WeakBorrowExt<str> for Weak<str> {
+    fn upgrade_state(&self) -> UpgradeState<str> {
+        upgrade_state(self, core::mem::align_of::<u8>())
+    }
+}
+
+fn upgrade_state<T: ?Sized>(weak: &Weak<T>, align: usize) -> UpgradeState<T> {
+    if let Some(rc) = weak.upgrade() {
+        return UpgradeState::Alive(rc);
+    }
+    let raw = Weak::as_ptr(weak);
+    // `Weak::new` doesn't allocate, and uses this address instead.
+    if raw.cast::<()>().addr() == usize::MAX {
+        return UpgradeState::Dead;
+    }
+    unsafe {
+        // Only the counts are accessed, so the value's type is irrelevant.
+        let rc_box = hack::raw_to_rc_box_align(raw, align) as *mut hack::RcBox<()>;
+        if (*rc_box).weak.get() > hack::BORROWED_WEAK {
+            UpgradeState::Borrowed
+        } else {
+            UpgradeState::Dead
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BorrowRefMut, RcBorrowMut, UpgradeState, WeakBorrowExt};
+    use std::rc::{Rc, Weak};
+    use std::vec;
+
+    #[test]
+    fn upgrade_state() {
+        let mut rc = Rc::new(1);
+        let weak = Rc::downgrade(&rc);
+        assert!(matches!(weak.upgrade_state(), UpgradeState::Alive(rc) if *rc == 1));
+
+        let mutable = Rc::borrow_mut(&mut rc);
+        assert!(matches!(weak.upgrade_state(), UpgradeState::Borrowed));
+        drop(mutable);
+        assert_eq!(*weak.upgrade_state().alive().unwrap(), 1);
+
+        drop(rc);
+        assert!(matches!(weak.upgrade_state(), UpgradeState::Dead));
+    }
+
+    #[test]
+    fn dangling() {
+        let weak = Weak::<u64>::new();
+        assert!(matches!(weak.upgrade_state(), UpgradeState::Dead));
+    }
+
+    #[test]
+    fn split() {
+        let mut rc: Rc<[u16]> = vec![1, 2].into();
+        let weak = Rc::downgrade(&rc);
+        let (a, b) = BorrowRefMut::map_split(Rc::borrow_mut(&mut rc), |s| s.split_at_mut(1));
+        drop(a);
+        assert!(matches!(weak.upgrade_state(), UpgradeState::Borrowed));
+        drop(b);
+        assert!(matches!(weak.upgrade_state(), UpgradeState::Alive(_)));
+    }
+
+    #[test]
+    fn owned() {
+        let rc: Rc<str> = "asdf".into();
+        let weak = Rc::downgrade(&rc);
+        let mut owned = Rc::into_borrow_mut(rc).unwrap();
+        owned.make_ascii_uppercase();
+        assert!(matches!(weak.upgrade_state(), UpgradeState::Borrowed));
+        drop(owned);
+        assert!(matches!(weak.upgrade_state(), UpgradeState::Dead));
+    }
+}